@@ -0,0 +1,257 @@
+use std::cmp::{min, max};
+
+use buffer::Buffer;
+use drawer::Drawer;
+use editor::Editor;
+use operator;
+use operator::Region;
+use register::Clip;
+
+// A rectangle selected with <C-v>: lines top to bottom and screen columns
+// left to right, both inclusive.
+#[derive(Clone, Copy)]
+pub struct Block {
+    pub top: i32,
+    pub bottom: i32,
+    pub left: i32,
+    pub right: i32,
+}
+
+// Text being typed by I or A in block mode, copied to the block's other
+// lines on <Escape>.
+pub struct BlockInsert {
+    pub buffer_index: usize,
+    pub top: i32,
+    pub bottom: i32,
+    pub column: i32,
+    pub x: i32,
+    // A pads short lines out to the column; I leaves them alone
+    pub append: bool,
+}
+
+// The block between the mark and the cursor, both given as (y, x).
+pub fn selection(buffer: &Buffer, mark: (i32, i32), cursor: (i32, i32)) -> Block {
+    // a tab covers every column it's drawn over
+    let span = |(y, x): (i32, i32)| (buffer.display_col(x, y), buffer.display_col(x + 1, y) - 1);
+    let (mark_left, mark_right) = span(mark);
+    let (cursor_left, cursor_right) = span(cursor);
+    Block {
+        top: min(mark.0, cursor.0),
+        bottom: max(mark.0, cursor.0),
+        left: min(mark_left, cursor_left),
+        right: max(mark_right, cursor_right),
+    }
+}
+
+// The characters of line y inside the block's columns, from start up to
+// but not including end.
+pub fn columns(buffer: &Buffer, block: &Block, y: i32) -> (i32, i32) {
+    let length = buffer.lines[y as usize].len() as i32;
+    let start = (0..length).find(|&x| buffer.display_col(x + 1, y) > block.left).unwrap_or(length);
+    let end = (start..length).find(|&x| buffer.display_col(x, y) > block.right).unwrap_or(length);
+    (start, end)
+}
+
+impl Editor {
+    pub fn visual_block(&mut self) -> Option<Block> {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        window.mark.map(|mark| selection(buffer, mark, (window.cursor_y, window.cursor_x)))
+    }
+
+    // An operator on a block acts on each line's piece of it. Operators
+    // without a block form act on the block's whole lines.
+    pub fn apply_block_operator(&mut self, name: &str, block: Block) {
+        match name {
+            "operator-delete" | "operator-change" | "operator-yank" => {
+                let clip = {
+                    let window = self.window_tree.find_active_window().unwrap();
+                    let ref buffer = self.buffers[window.buffer_index as usize];
+                    let rows: Vec<String> = (block.top..(block.bottom + 1)).map(|y| {
+                        let (start, end) = columns(buffer, &block, y);
+                        buffer.text_between((start, y), (end, y))
+                    }).collect();
+                    Clip { text: rows.join("\n"), kind: "blockwise" }
+                };
+                let register = self.window_tree.find_active_window().unwrap().register.take();
+                if let Err(error) = self.registers.store(register, clip, name == "operator-yank") {
+                    self.message = Some(error);
+                }
+            },
+            "operator-indent" | "operator-dedent" | "operator-lowercase" | "operator-uppercase" | "operator-switch-case" => (),
+            _ => {
+                let region = Region { start: (0, block.top), end: (0, block.bottom), linewise: true };
+                self.apply_operator(name, region);
+                return;
+            }
+        }
+        {
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref mut buffer = self.buffers[window.buffer_index as usize];
+            window.mode = "normal".to_string();
+            window.operator = None;
+            let unit = buffer.indent_unit();
+            buffer.begin_group();
+            for y in block.top..(block.bottom + 1) {
+                let (start, end) = columns(buffer, &block, y);
+                let length = buffer.lines[y as usize].len() as i32;
+                match name {
+                    "operator-delete" | "operator-change" => {
+                        buffer.remove_text(start, y, (end - start) as usize, true);
+                    },
+                    "operator-indent" => {
+                        if start < length {
+                            buffer.insert(&unit, start, y, true);
+                        }
+                    },
+                    "operator-dedent" => {
+                        // a tab, or up to a tab's width of spaces, from the block's edge
+                        let n = match buffer.char_at(start, y) {
+                            Some('\t') => 1,
+                            _ => buffer.lines[y as usize].iter().skip(start as usize)
+                                .take(unit.len())
+                                .take_while(|c| c.ch == ' ')
+                                .count(),
+                        };
+                        buffer.remove_text(start, y, n, true);
+                    },
+                    "operator-lowercase" | "operator-uppercase" | "operator-switch-case" => {
+                        let text = buffer.text_between((start, y), (end, y));
+                        let changed = operator::change_case(name, &text);
+                        if changed != text {
+                            buffer.remove_text(start, y, text.chars().count(), true);
+                            buffer.insert(&changed, start, y, true);
+                        }
+                    },
+                    _ => ()
+                }
+            }
+            buffer.end_group();
+            window.cursor_y = block.top;
+            window.cursor_x = min(columns(buffer, &block, block.top).0, buffer.eol(block.top));
+            window.col = window.cursor_x;
+            window.row = window.cursor_y;
+        }
+        if name == "operator-change" {
+            self.start_block_insert(block, false);
+        }
+    }
+
+    // I and A: insert before or append after the block on its first line;
+    // the text is repeated on the other lines when insert mode ends.
+    pub fn start_block_insert(&mut self, block: Block, append: bool) {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref mut buffer = self.buffers[window.buffer_index as usize];
+        let column = if append { block.right + 1 } else { block.left };
+        let y = block.top;
+        if append {
+            buffer.pad_to_col(column, y);
+        }
+        let x = buffer.x_at_col(column, y);
+        window.mark = None;
+        window.mode = "insert".to_string();
+        window.cursor_x = x;
+        window.cursor_y = y;
+        window.col = x;
+        self.block_insert = Some(BlockInsert {
+            buffer_index: window.buffer_index as usize,
+            top: y,
+            bottom: block.bottom,
+            column: column,
+            x: x,
+            append: append,
+        });
+    }
+
+    // Copies what was typed after I or A to the rest of the block, unless
+    // the cursor left the line it was typed on.
+    pub fn finish_block_insert(&mut self) {
+        let insert = match self.block_insert.take() {
+            Some(insert) => insert,
+            None => { return; }
+        };
+        let window = self.window_tree.find_active_window().unwrap();
+        if window.buffer_index as usize != insert.buffer_index || window.cursor_y != insert.top || window.cursor_x <= insert.x {
+            return;
+        }
+        let ref mut buffer = self.buffers[insert.buffer_index];
+        let text = buffer.text_between((insert.x, insert.top), (window.cursor_x, insert.top));
+        buffer.begin_group();
+        for y in (insert.top + 1)..min(insert.bottom + 1, buffer.eof()) {
+            if buffer.display_col(buffer.lines[y as usize].len() as i32, y) < insert.column {
+                if !insert.append {
+                    continue;
+                }
+                buffer.pad_to_col(insert.column, y);
+            }
+            let x = buffer.x_at_col(insert.column, y);
+            buffer.insert(&text, x, y, true);
+        }
+        buffer.end_group();
+    }
+
+    // Emacs's open-rectangle: pushes the text in the block to the right,
+    // leaving it blank.
+    pub fn open_rectangle(&mut self, block: Block) {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref mut buffer = self.buffers[window.buffer_index as usize];
+        let blank: String = (block.left..(block.right + 1)).map(|_| " ").collect();
+        buffer.begin_group();
+        for y in block.top..(block.bottom + 1) {
+            // lines that end before the block are left alone
+            if buffer.display_col(buffer.lines[y as usize].len() as i32, y) > block.left {
+                let x = buffer.x_at_col(block.left, y);
+                buffer.insert(&blank, x, y, true);
+            }
+        }
+        buffer.end_group();
+        window.mode = "normal".to_string();
+        window.mark = None;
+        window.cursor_y = block.top;
+        window.cursor_x = min(buffer.x_at_col(block.left, block.top), buffer.eol(block.top));
+        window.col = window.cursor_x;
+    }
+
+    // Emacs's string-rectangle: asks for a string to replace each line's
+    // piece of the block with.
+    pub fn start_string_rectangle(&mut self, block: Block) {
+        self.rectangle = Some(block);
+        self.window_tree.find_active_window().unwrap().mode = "string_rectangle".to_string();
+        self.drawer = Some(Drawer::new_prompt("string_rectangle", "String rectangle: "));
+    }
+
+    pub fn handle_string_rectangle(&mut self, key: &str) {
+        match key {
+            "<Escape>" | "<C-g>" => { self.end_string_rectangle(); },
+            "<Enter>" => {
+                let text = self.drawer.as_ref().unwrap().value.clone();
+                let block = self.rectangle.unwrap();
+                self.end_string_rectangle();
+                let window = self.window_tree.find_active_window().unwrap();
+                let ref mut buffer = self.buffers[window.buffer_index as usize];
+                buffer.begin_group();
+                for y in block.top..(block.bottom + 1) {
+                    buffer.pad_to_col(block.left, y);
+                    let (start, end) = columns(buffer, &block, y);
+                    buffer.remove_text(start, y, (end - start) as usize, true);
+                    if text.len() > 0 {
+                        buffer.insert(&text, start, y, true);
+                    }
+                }
+                buffer.end_group();
+                window.cursor_y = block.top;
+                window.cursor_x = min(buffer.x_at_col(block.left, block.top), buffer.eol(block.top));
+                window.col = window.cursor_x;
+            },
+            _ => { self.drawer.as_mut().unwrap().handle_key(key); }
+        }
+    }
+
+    fn end_string_rectangle(&mut self) {
+        self.drawer = None;
+        self.rectangle = None;
+        let window = self.window_tree.find_active_window().unwrap();
+        window.mode = "normal".to_string();
+        window.mark = None;
+    }
+}
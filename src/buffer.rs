@@ -1,12 +1,18 @@
 use syntect;
 use syntect::easy::HighlightLines;
-use std::cmp::{max};
-// use std::fs::File;
-// use std::io::Write;
+use syntect::parsing::{ParseState, ScopeStack};
+use std::cell::RefCell;
+use std::cmp::{min, max};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Write;
 use cell::Cell;
-use std::path::PathBuf;
+use comment;
+use std::path::{Path, PathBuf};
 use util;
 use transaction::Transaction;
+use config::Settings;
 
 pub struct Buffer {
     pub lines: Vec<Vec<Cell>>,
@@ -14,6 +20,21 @@ pub struct Buffer {
     pub highlighter: Option<syntect::parsing::SyntaxDefinition>,
     pub ts: Option<syntect::highlighting::ThemeSet>,
     pub transactions: Vec<Transaction>,
+    pub group: usize,
+    pub group_depth: usize,
+    pub theme: String,
+    pub settings: Settings,
+    // set on the scratch buffers that edit a macro
+    pub macro_register: Option<char>,
+    // marks set with m, and ' and ^ set by jumps and inserts, as (y, x).
+    // They move with their lines.
+    pub marks: HashMap<char, (i32, i32)>,
+    // syntect's state at the start of each line, as far down as it's been
+    // needed; an edit drops the states after its line. Filled in while the
+    // buffer is only being read.
+    pub parse_states: RefCell<Vec<(ParseState, ScopeStack)>>,
+    // the undo group of the last change when the buffer was last written
+    pub saved_group: Option<usize>,
 }
 
 impl Buffer {
@@ -24,21 +45,38 @@ impl Buffer {
             highlighter: highlighter,
             ts: ts,
             transactions: vec![],
+            group: 0,
+            group_depth: 0,
+            theme: "base16-ocean.dark".to_string(),
+            settings: Settings::new(),
+            macro_register: None,
+            marks: HashMap::new(),
+            parse_states: RefCell::new(vec![]),
+            saved_group: None,
         }
     }
 
-    pub fn save(&self) {
-        // match File::create(&self.path) {
-        //     Ok(mut f) => {
-        //         let mut lns = self.lines.join(&Cell::new('\n', 1));
-        //         // lns.push('\n');
-        //         // match f.write_all(lns) {
-        //         //     Ok(_) => (),
-        //         //     Err(e) => panic!(e)
-        //         // };
-        //     },
-        //     Err(_) => ()
-        // }
+    // Writes every line, each ending in a newline. Returns the number of
+    // bytes written.
+    pub fn write_to(&self, path: &Path) -> io::Result<usize> {
+        let mut text = String::new();
+        for y in 0..self.eof() {
+            text.push_str(&self.line_text(y));
+            text.push('\n');
+        }
+        let mut f = File::create(path)?;
+        f.write_all(text.as_bytes())?;
+        Ok(text.len())
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_group = self.transactions.last().map(|t| t.group);
+    }
+
+    // Whether there are changes since the buffer was last written; undoing
+    // back to that point counts as none.
+    pub fn is_modified(&self) -> bool {
+        self.transactions.last().map(|t| t.group) != self.saved_group
     }
 
     pub fn char_at(&mut self, x: i32, y: i32) -> Option<char> {
@@ -51,32 +89,68 @@ impl Buffer {
 
     pub fn remove(&mut self, x: i32, y: i32, t: bool) {
         let mut line = self.lines[y as usize].clone();
-        if t {
-            match self.char_at(x, y) {
-                Some(ch) => {
-                    self.transactions.push(Transaction{
-                        x: x,
-                        y: y,
-                        add: false,
-                        text: ch.to_string(),
-                    });
-                },
-                None => {}
-            }
-        }
         if x == -1 || line.len() == 0 {
-            self.lines[(y - 1) as usize].append(&mut line);
-            self.remove_line(y as usize);
+            let prev = (y - 1) as usize;
+            let length = self.lines[prev].len() as i32;
+            if t {
+                self.record(length, y - 1, false, "\n".to_string());
+            }
+            self.lines[prev].append(&mut line);
+            self.lines.remove(y as usize);
+            self.parse_states.get_mut().truncate(y as usize);
+            for mark in self.marks.values_mut() {
+                if mark.0 == y {
+                    *mark = (y - 1, length + mark.1);
+                } else if mark.0 > y {
+                    mark.0 -= 1;
+                }
+            }
+            self.highlight_line(y - 1);
         } else {
+            if t {
+                if let Some(ch) = self.char_at(x, y) {
+                    self.record(x, y, false, ch.to_string());
+                }
+            }
             let (a, b) = line.split_at(x as usize);
             let mut new = a.to_vec();
             new.append(&mut (&b[1..]).to_vec());
             self.lines[y as usize] = new;
+            self.parse_states.get_mut().truncate(y as usize + 1);
+            self.highlight_line(y);
+        }
+    }
+
+    // Removes `n` characters starting at (x, y), counting line breaks as one.
+    pub fn remove_text(&mut self, x: i32, y: i32, n: usize, t: bool) {
+        for _ in 0..n {
+            if (x as usize) < self.lines[y as usize].len() {
+                self.remove(x, y, t);
+            } else if ((y + 1) as usize) < self.lines.len() {
+                self.remove(-1, y + 1, t);
+            }
         }
-        self.highlight_line(y);
     }
 
+    // `c` may span several lines.
     pub fn insert(&mut self, c: &str, x: i32, y: i32, t: bool) {
+        let mut pieces = c.split('\n');
+        let first = pieces.next().unwrap();
+        self.insert_cells(first, x, y);
+        let mut end_x = x + first.chars().count() as i32;
+        let mut end_y = y;
+        for piece in pieces {
+            self.split_line(end_x, end_y);
+            end_y += 1;
+            self.insert_cells(piece, 0, end_y);
+            end_x = piece.chars().count() as i32;
+        }
+        if t {
+            self.record(x, y, true, c.to_string());
+        }
+    }
+
+    fn insert_cells(&mut self, c: &str, x: i32, y: i32) {
         let mut line = self.lines[y as usize].clone();
         let mut new = vec![];
         if line.len() == 0 {
@@ -90,21 +164,61 @@ impl Buffer {
         }
 
         self.lines[y as usize] = new;
+        self.parse_states.get_mut().truncate(y as usize + 1);
         self.highlight_line(y);
-        if t {
-            self.transactions.push(Transaction{
-                x: x,
-                y: y,
-                add: true,
-                text: c.to_string(),
-            });
+    }
+
+    fn record(&mut self, x: i32, y: i32, add: bool, text: String) {
+        if self.group_depth == 0 {
+            self.group += 1;
+        }
+        self.transactions.push(Transaction{
+            x: x,
+            y: y,
+            add: add,
+            text: text,
+            group: self.group,
+        });
+    }
+
+    // Everything recorded until the matching `end_group` is undone in one go.
+    // Groups nest; only the outermost one counts.
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group += 1;
         }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        if self.group_depth > 0 {
+            self.group_depth -= 1;
+        }
+    }
+
+    // Reverts the last group of transactions, returning where it started.
+    pub fn undo(&mut self) -> Option<(i32, i32)> {
+        let group = match self.transactions.last() {
+            Some(t) => t.group,
+            None => { return None; }
+        };
+        let mut position = None;
+        while self.transactions.last().map(|t| t.group == group).unwrap_or(false) {
+            let t = self.transactions.pop().unwrap();
+            if t.add {
+                self.remove_text(t.x, t.y, t.text.chars().count(), false);
+            } else {
+                self.insert(t.text.as_str(), t.x, t.y, false);
+            }
+            position = Some((t.x, t.y));
+        }
+        position
     }
 
     pub fn highlight_line(&mut self, y: i32) {
         match self.highlighter {
             Some(ref highlighter) => {
-                let mut h = HighlightLines::new(&highlighter, &self.ts.as_ref().unwrap().themes["base16-ocean.dark"]);
+                let mut h = HighlightLines::new(&highlighter, &self.ts.as_ref().unwrap().themes[&self.theme]);
                 let line_string: String = self.lines[y as usize].iter().cloned().map(|c| c.ch).collect();
                 self.lines[y as usize] = vec![];
                 let ranges = h.highlight(line_string.as_str());
@@ -119,15 +233,194 @@ impl Buffer {
         }
     }
 
-    pub fn insert_newline(&mut self, x: i32, y: i32) {
+    pub fn highlight_all(&mut self) {
+        for y in 0..self.lines.len() {
+            self.highlight_line(y as i32);
+        }
+    }
+
+    pub fn syntax_name(&self) -> Option<&str> {
+        self.highlighter.as_ref().map(|h| h.name.as_str())
+    }
+
+    // The screen column of `x`, with tabs expanded.
+    pub fn display_col(&self, x: i32, y: i32) -> i32 {
+        let mut col = 0;
+        for cell in self.lines[y as usize].iter().take(max(0, x) as usize) {
+            if cell.ch == '\t' {
+                col += self.settings.tab_width - col % self.settings.tab_width;
+            } else {
+                col += 1;
+            }
+        }
+        col + max(0, x - self.lines[y as usize].len() as i32)
+    }
+
+    // The first character at or past a screen column, or the end of the line.
+    pub fn x_at_col(&self, column: i32, y: i32) -> i32 {
+        let length = self.lines[y as usize].len() as i32;
+        (0..length).find(|&x| self.display_col(x, y) >= column).unwrap_or(length)
+    }
+
+    // Adds spaces to the end of line y until it reaches the column.
+    pub fn pad_to_col(&mut self, column: i32, y: i32) {
+        let length = self.lines[y as usize].len() as i32;
+        let width = self.display_col(length, y);
+        if width < column {
+            let padding: String = (0..(column - width)).map(|_| " ").collect();
+            self.insert(&padding, length, y, true);
+        }
+    }
+
+    // One level of indentation, as > adds it to the start of a line.
+    pub fn indent_unit(&self) -> String {
+        self.make_indent(self.settings.shift_width())
+    }
+
+    // Whitespace `width` columns wide: tabs as far as they go unless
+    // expand_tab is set, then spaces.
+    pub fn make_indent(&self, width: i32) -> String {
+        let tab_width = self.settings.tab_width;
+        let tabs = if self.settings.expand_tab { 0 } else { width / tab_width };
+        let spaces = width - tabs * tab_width;
+        (0..tabs).map(|_| "\t").chain((0..spaces).map(|_| " ")).collect()
+    }
+
+    // How many columns the indentation of line y takes up.
+    pub fn indent_width(&self, y: i32) -> i32 {
+        let n = self.lines[y as usize].iter().take_while(|c| c.ch == ' ' || c.ch == '\t').count();
+        self.display_col(n as i32, y)
+    }
+
+    // Replaces line y's indentation with one `width` columns wide. Returns
+    // how many characters longer the line got.
+    pub fn set_indent_width(&mut self, y: i32, width: i32) -> i32 {
+        let old = self.lines[y as usize].iter().take_while(|c| c.ch == ' ' || c.ch == '\t').count();
+        let indent = self.make_indent(max(0, width));
+        let current: String = self.lines[y as usize].iter().take(old).map(|c| c.ch).collect();
+        if indent == current {
+            return 0;
+        }
+        self.remove_text(0, y, old, true);
+        if indent.len() > 0 {
+            self.insert(&indent, 0, y, true);
+        }
+        indent.chars().count() as i32 - old as i32
+    }
+
+    pub fn insert_newline(&mut self, x: i32, y: i32, t: bool) {
+        self.split_line(x, y);
+        if t {
+            self.record(x, y, true, "\n".to_string());
+        }
+    }
+
+    fn split_line(&mut self, x: i32, y: i32) {
         let line = self.lines[y as usize].clone();
         let (a, b) = line.split_at(x as usize);
         self.lines[y as usize] = a.to_vec();
         self.lines.insert((y + 1) as usize, b.to_vec());
+        self.parse_states.get_mut().truncate(y as usize + 1);
+        for mark in self.marks.values_mut() {
+            if mark.0 == y && mark.1 >= x {
+                *mark = (y + 1, mark.1 - x);
+            } else if mark.0 > y {
+                mark.0 += 1;
+            }
+        }
     }
 
-    pub fn remove_line(&mut self, index: usize) {
+    // The last line is emptied rather than removed.
+    pub fn remove_line(&mut self, index: usize, t: bool) {
+        let text: String = self.lines[index].iter().map(|c| c.ch).collect();
+        if self.lines.len() == 1 {
+            if t && text.len() > 0 {
+                self.record(0, 0, false, text);
+            }
+            self.lines[0] = vec![];
+            self.parse_states.get_mut().truncate(1);
+            for mark in self.marks.values_mut() {
+                mark.1 = 0;
+            }
+            return;
+        }
+        if t {
+            if index + 1 < self.lines.len() {
+                self.record(0, index as i32, false, text + "\n");
+            } else {
+                let length = self.lines[index - 1].len() as i32;
+                self.record(length, (index - 1) as i32, false, "\n".to_string() + &text);
+            }
+        }
         self.lines.remove(index);
+        self.parse_states.get_mut().truncate(index + 1);
+        // like vim, a mark goes with its line
+        let y = index as i32;
+        self.marks.retain(|_, mark| mark.0 != y);
+        for mark in self.marks.values_mut() {
+            if mark.0 > y {
+                mark.0 -= 1;
+            }
+        }
+    }
+
+    // The text from `start` up to but not including `end`, as (x, y).
+    pub fn text_between(&self, start: (i32, i32), end: (i32, i32)) -> String {
+        let mut text = String::new();
+        for y in start.1..(end.1 + 1) {
+            let ref line = self.lines[y as usize];
+            let from = if y == start.1 { max(0, start.0) as usize } else { 0 };
+            let to = if y == end.1 { max(0, end.0) as usize } else { line.len() };
+            let to = min(to, line.len());
+            if from < to {
+                text.extend(line[from..to].iter().map(|c| c.ch));
+            }
+            if y != end.1 {
+                text.push('\n');
+            }
+        }
+        text
+    }
+
+    // Joins line y with the next as J does: the next line's indent goes,
+    // and so does its comment leader if it's the same as line y's. One
+    // space goes between them, unless there's already one or the next line
+    // is empty or starts with `)`. With `spaces` false (gJ) the text is left
+    // as it is. Returns where the lines meet.
+    pub fn join_line(&mut self, y: i32, spaces: bool) -> i32 {
+        let length = self.lines[y as usize].len() as i32;
+        if !spaces {
+            self.remove_text(length, y, 1, true);
+            return length;
+        }
+        if let Some(leader) = comment::leader(&self.settings.comment, &self.line_text(y)) {
+            let next = self.line_text(y + 1);
+            if comment::leader(&self.settings.comment, &next) == Some(leader.clone()) {
+                let x = next.chars().take_while(|c| c.is_whitespace()).count();
+                self.remove_text(x as i32, y + 1, leader.chars().count(), true);
+            }
+        }
+        self.remove_text(length, y, 1, true);
+        let indent = self.lines[y as usize][(length as usize)..].iter().take_while(|c| c.ch == ' ' || c.ch == '\t').count();
+        self.remove_text(length, y, indent, true);
+        let rest = self.lines[y as usize].get(length as usize).map(|c| c.ch);
+        let ends_blank = length > 0 && self.lines[y as usize][(length - 1) as usize].ch.is_whitespace();
+        if length > 0 && rest.is_some() && rest != Some(')') && !ends_blank {
+            self.insert(" ", length, y, true);
+        }
+        length
+    }
+
+    pub fn line_text(&self, y: i32) -> String {
+        self.lines[y as usize].iter().map(|c| c.ch).collect()
+    }
+
+    pub fn first_non_blank(&self, y: i32) -> i32 {
+        let ref line = self.lines[y as usize];
+        match line.iter().position(|c| !c.ch.is_whitespace()) {
+            Some(x) => x as i32,
+            None => max(0, line.len() as i32 - 1),
+        }
     }
 
     pub fn eol(&self, y: i32) -> i32 {
@@ -0,0 +1,164 @@
+use std::env;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// The ways to reach the system clipboard. "auto" picks one at startup and
+// "command" runs clipboard_copy and clipboard_paste from the config.
+pub static PROVIDERS: &'static [&'static str] = &[
+    "auto",
+    "command",
+    "internal",
+    "osc52",
+    "tmux",
+    "wayland",
+    "xclip",
+    "xsel",
+];
+
+pub struct Clipboard {
+    pub provider: String,
+    copy_command: String,
+    paste_command: String,
+    // the last text copied, for providers that can't be read back from
+    text: String,
+}
+
+impl Clipboard {
+    pub fn new(provider: &str, copy_command: &str, paste_command: &str) -> Clipboard {
+        let provider = if provider == "auto" { detect(copy_command) } else { provider };
+        let (copy, paste) = match provider {
+            "xsel" => ("xsel --clipboard --input", "xsel --clipboard --output"),
+            "xclip" => ("xclip -selection clipboard -in", "xclip -selection clipboard -out"),
+            "wayland" => ("wl-copy", "wl-paste --no-newline"),
+            "tmux" => ("tmux load-buffer -", "tmux save-buffer -"),
+            "command" => (copy_command, paste_command),
+            _ => ("", ""),
+        };
+        Clipboard {
+            provider: provider.to_string(),
+            copy_command: copy.to_string(),
+            paste_command: paste.to_string(),
+            text: String::new(),
+        }
+    }
+
+    pub fn copy(&mut self, text: &str) -> Result<(), String> {
+        self.text = text.to_string();
+        match self.provider.as_str() {
+            "internal" => Ok(()),
+            "osc52" => osc52(text).map_err(|e| format!("clipboard: {}", e)),
+            _ => run_copy(&self.copy_command, text),
+        }
+    }
+
+    pub fn paste(&self) -> Result<String, String> {
+        match self.provider.as_str() {
+            // terminals rarely let programs read the clipboard
+            "internal" | "osc52" => Ok(self.text.clone()),
+            _ => run_paste(&self.paste_command),
+        }
+    }
+}
+
+// Explicit commands win; then whatever the display server offers, the
+// terminal over SSH, tmux, and finally just memory.
+fn detect(copy_command: &str) -> &'static str {
+    let set = |name: &str| env::var(name).map(|v| v.len() > 0).unwrap_or(false);
+    if copy_command.len() > 0 {
+        "command"
+    } else if set("WAYLAND_DISPLAY") && installed("wl-copy") {
+        "wayland"
+    } else if set("DISPLAY") && installed("xsel") {
+        "xsel"
+    } else if set("DISPLAY") && installed("xclip") {
+        "xclip"
+    } else if set("SSH_TTY") || set("SSH_CONNECTION") {
+        "osc52"
+    } else if set("TMUX") {
+        "tmux"
+    } else {
+        "internal"
+    }
+}
+
+fn installed(program: &str) -> bool {
+    match env::var("PATH") {
+        Ok(path) => path.split(':').any(|dir| Path::new(dir).join(program).is_file()),
+        Err(_) => false,
+    }
+}
+
+// `command` is a program and its arguments, e.g. "xsel --clipboard --input",
+// that reads the new clipboard contents from stdin.
+fn run_copy(command: &str, text: &str) -> Result<(), String> {
+    let mut args = command.split_whitespace();
+    let program = match args.next() {
+        Some(program) => program,
+        None => { return Err("clipboard: no copy command".to_string()); }
+    };
+    let mut child = Command::new(program)
+        .args(&args.collect::<Vec<&str>>())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("clipboard: can't run {}: {}", program, e))?;
+    child.stdin.take().unwrap().write_all(text.as_bytes())
+        .map_err(|e| format!("clipboard: {}: {}", program, e))?;
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        _ => Err(format!("clipboard: {} failed", program)),
+    }
+}
+
+// `command` prints the clipboard contents, e.g. "xsel --clipboard --output".
+fn run_paste(command: &str) -> Result<String, String> {
+    let mut args = command.split_whitespace();
+    let program = match args.next() {
+        Some(program) => program,
+        None => { return Err("clipboard: no paste command".to_string()); }
+    };
+    let output = Command::new(program)
+        .args(&args.collect::<Vec<&str>>())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("clipboard: can't run {}: {}", program, e))?;
+    if !output.status.success() {
+        return Err(format!("clipboard: {} failed", program));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Asks the terminal to set its clipboard, through tmux if need be.
+fn osc52(text: &str) -> io::Result<()> {
+    let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    let sequence = if env::var("TMUX").is_ok() {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace("\x1b", "\x1b\x1b"))
+    } else {
+        sequence
+    };
+    let mut out = io::stdout();
+    out.write_all(sequence.as_bytes())?;
+    out.flush()
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const DIGITS: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut result = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                result.push(DIGITS[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
@@ -0,0 +1,394 @@
+use std::path::PathBuf;
+
+use editor::{Editor, NORTH, SOUTH, EAST, WEST};
+use buffer::Buffer;
+use cell::Cell;
+use keymap;
+use operator;
+use text_object;
+use cursors;
+use config::Config;
+use util;
+
+// Every command that can be bound to a key, with a short description.
+pub static COMMANDS: &'static [(&'static str, &'static str)] = &[
+    ("add-cursor-above", "Add a cursor on the line above"),
+    ("add-cursor-below", "Add a cursor on the line below"),
+    ("add-cursor-next-match", "Add a cursor at the next occurrence of the word or selection"),
+    ("add-cursors-to-lines", "Add a cursor on each selected line"),
+    ("append-eol", "Insert at the end of the line"),
+    ("append", "Insert after the cursor"),
+    ("around-angle", "The text in angle brackets, with them"),
+    ("around-brace", "The text in braces, with them"),
+    ("around-bracket", "The text in square brackets, with them"),
+    ("around-double-quote", "The text in double quotes, with them"),
+    ("around-function", "The function with its surroundings"),
+    ("around-paragraph", "The paragraph with its surroundings"),
+    ("around-paren", "The text in parentheses, with them"),
+    ("around-single-quote", "The text in single quotes, with them"),
+    ("around-tag", "The text in tags, with them"),
+    ("around-word", "The word with its surroundings"),
+    ("change-to-eol", "Change the rest of the line"),
+    ("clear-search-highlight", "Stop highlighting the last search"),
+    ("close-window", "Close the active window"),
+    ("command-line", "Type an ex command"),
+    ("comment-line", "Comment out the line, or uncomment it"),
+    ("dedent-line", "Remove a level of indentation from the line"),
+    ("decrement-number", "Subtract one, or the count, from the number under or after the cursor"),
+    ("delete-char", "Delete the character under the cursor"),
+    ("delete-to-eol", "Delete the rest of the line"),
+    ("delete-region", "Delete the visual selection"),
+    ("describe-bindings", "List the active key bindings"),
+    ("edit-macro", "Edit the keys of a macro in a buffer"),
+    ("execute-command", "Pick a command by name and run it"),
+    ("find-char", "Move to the next occurrence of a character"),
+    ("find-char-backwards", "Move to the previous occurrence of a character"),
+    ("find-files", "Open a file"),
+    ("focus-east", "Focus the window to the right"),
+    ("focus-north", "Focus the window above"),
+    ("focus-south", "Focus the window below"),
+    ("focus-west", "Focus the window to the left"),
+    ("format-line", "Fill the line to the text width"),
+    ("goto-first-line", "Move to the first line, or line N with a count"),
+    ("goto-last-line", "Move to the last line, or line N with a count"),
+    ("inner-angle", "The text in angle brackets"),
+    ("inner-brace", "The text in braces"),
+    ("inner-bracket", "The text in square brackets"),
+    ("inner-double-quote", "The text in double quotes"),
+    ("inner-function", "The function without its surroundings"),
+    ("inner-paragraph", "The paragraph without its surroundings"),
+    ("inner-paren", "The text in parentheses"),
+    ("inner-single-quote", "The text in single quotes"),
+    ("inner-tag", "The text in tags"),
+    ("inner-word", "The word without its surroundings"),
+    ("goto-mark", "Go to a mark"),
+    ("goto-mark-line", "Go to the line of a mark"),
+    ("increment-number", "Add one, or the count, to the number under or after the cursor"),
+    ("indent-line", "Indent the line a level"),
+    ("insert-bol", "Insert before the first non-blank of the line"),
+    ("insert-mode", "Insert before the cursor"),
+    ("join-lines", "Join the line with the next, or the selected lines"),
+    ("join-lines-without-spaces", "Join lines as they are, without adding or removing spaces"),
+    ("jump-back", "Go back to where the cursor jumped from"),
+    ("jump-forward", "Go forward again through the jump list"),
+    ("match-bracket", "Move to the matching bracket"),
+    ("move-big-word-backward", "Move to the start of the previous blank-separated word"),
+    ("move-big-word-end", "Move to the end of the blank-separated word"),
+    ("move-big-word-forward", "Move to the start of the next blank-separated word"),
+    ("move-bol", "Move to the beginning of the line"),
+    ("move-down", "Move down one line"),
+    ("move-eol", "Move to the end of the line"),
+    ("move-left", "Move left one character"),
+    ("move-paragraph-backward", "Move to the start of the paragraph"),
+    ("move-paragraph-forward", "Move to the end of the paragraph"),
+    ("move-right", "Move right one character"),
+    ("move-sentence-backward", "Move to the start of the sentence"),
+    ("move-sentence-forward", "Move to the start of the next sentence"),
+    ("move-up", "Move up one line"),
+    ("move-window-bottom", "Move to the last line in the window"),
+    ("move-window-middle", "Move to the middle line in the window"),
+    ("move-window-top", "Move to the first line in the window"),
+    ("move-word-backward", "Move to the start of the previous word"),
+    ("move-word-end", "Move to the end of the word"),
+    ("move-word-forward", "Move to the start of the next word"),
+    ("next-theme", "Switch to the next color theme"),
+    ("normal-mode", "Return to normal mode"),
+    ("open-line-above", "Open a line above and insert"),
+    ("open-line-below", "Open a line below and insert"),
+    ("operator-change", "Change the text a motion covers"),
+    ("operator-comment", "Comment out the lines a motion covers, or uncomment them"),
+    ("operator-dedent", "Shift the lines a motion covers left"),
+    ("operator-delete", "Delete the text a motion covers"),
+    ("operator-format", "Fill the lines a motion covers to the text width"),
+    ("operator-indent", "Shift the lines a motion covers right"),
+    ("operator-lowercase", "Lowercase the text a motion covers"),
+    ("operator-reindent", "Work out the indentation of the lines a motion covers"),
+    ("operator-switch-case", "Switch the case of the text a motion covers"),
+    ("operator-uppercase", "Uppercase the text a motion covers"),
+    ("operator-yank", "Copy the text a motion covers to a register"),
+    ("page-down", "Scroll down one page"),
+    ("page-up", "Scroll up one page"),
+    ("list-registers", "Show the registers and the kill ring"),
+    ("paste", "Paste after the cursor, or below the line for whole lines"),
+    ("paste-before", "Paste before the cursor, or above the line for whole lines"),
+    ("play-macro", "Play a macro, or the last one with @"),
+    ("quit", "Exit the editor"),
+    ("record-macro", "Start recording a macro, or stop if recording"),
+    ("reload-config", "Read the config file again"),
+    ("repeat-change", "Repeat the last change"),
+    ("replace-char", "Replace the character under the cursor"),
+    ("replace-mode", "Type over the text"),
+    ("save", "Save the buffer"),
+    ("search-backward", "Search backwards for a pattern"),
+    ("select-register", "Use a register for the next yank, delete or paste"),
+    ("search-forward", "Search for a pattern"),
+    ("search-next", "Go to the next match of the last search"),
+    ("search-previous", "Go to the previous match of the last search"),
+    ("search-word-backward", "Search backwards for the word under the cursor"),
+    ("search-word-forward", "Search for the word under the cursor"),
+    ("set-mark", "Mark the cursor's place"),
+    ("split-east", "Split the window to the right"),
+    ("split-north", "Split the window above"),
+    ("split-south", "Split the window below"),
+    ("split-west", "Split the window to the left"),
+    ("substitute-char", "Delete the character under the cursor and insert"),
+    ("substitute-line", "Change the whole line"),
+    ("switch-case", "Switch the case of the character under the cursor"),
+    ("till-char", "Move to just before the next occurrence of a character"),
+    ("till-char-backwards", "Move to just after the previous occurrence of a character"),
+    ("undo", "Undo the last change"),
+    ("visual-block-mode", "Start a visual selection of a rectangle"),
+    ("visual-line-mode", "Start a visual selection of whole lines"),
+    ("visual-mode", "Start a visual selection"),
+    ("wrap-selection", "Put a pair of brackets or quotes around the visual selection"),
+    ("yank-line", "Copy the line to a register"),
+    ("yank-pop", "Replace the text just pasted with the previous kill"),
+    ("yank-region", "Copy the visual selection to a register"),
+];
+
+pub fn is_command(name: &str) -> bool {
+    COMMANDS.iter().any(|&(n, _)| n == name)
+}
+
+// Commands that run once per count, as a single undo step.
+static REPEATED: &'static [&'static str] = &[
+    "move-down",
+    "move-left",
+    "move-right",
+    "move-up",
+    "page-down",
+    "page-up",
+    "undo",
+];
+
+// Commands that leave the count for whatever they do next to use.
+static PASSES_COUNT: &'static [&'static str] = &[
+    "around-angle",
+    "around-brace",
+    "around-bracket",
+    "around-double-quote",
+    "around-function",
+    "around-paragraph",
+    "around-paren",
+    "around-single-quote",
+    "around-tag",
+    "around-word",
+    "change-to-eol",
+    "command-line",
+    "comment-line",
+    "decrement-number",
+    "delete-char",
+    "delete-to-eol",
+    "find-char",
+    "find-char-backwards",
+    "format-line",
+    "goto-first-line",
+    "goto-last-line",
+    "increment-number",
+    "inner-angle",
+    "inner-brace",
+    "inner-bracket",
+    "inner-double-quote",
+    "inner-function",
+    "inner-paragraph",
+    "inner-paren",
+    "inner-single-quote",
+    "inner-tag",
+    "inner-word",
+    "join-lines",
+    "join-lines-without-spaces",
+    "jump-back",
+    "jump-forward",
+    "match-bracket",
+    "move-big-word-backward",
+    "move-big-word-end",
+    "move-big-word-forward",
+    "move-paragraph-backward",
+    "move-paragraph-forward",
+    "move-sentence-backward",
+    "move-sentence-forward",
+    "move-window-bottom",
+    "move-window-middle",
+    "move-window-top",
+    "move-word-backward",
+    "move-word-end",
+    "move-word-forward",
+    "operator-change",
+    "operator-comment",
+    "operator-dedent",
+    "operator-delete",
+    "operator-format",
+    "operator-indent",
+    "operator-lowercase",
+    "operator-reindent",
+    "operator-switch-case",
+    "operator-uppercase",
+    "operator-yank",
+    "paste",
+    "paste-before",
+    "play-macro",
+    "repeat-change",
+    "replace-char",
+    "search-next",
+    "search-previous",
+    "search-word-backward",
+    "search-word-forward",
+    "select-register",
+    "substitute-char",
+    "substitute-line",
+    "switch-case",
+    "till-char",
+    "till-char-backwards",
+    "yank-line",
+];
+
+impl Editor {
+    pub fn run_command(&mut self, name: &str) {
+        let selection = self.visual_selection();
+        let mode = self.window_tree.find_active_window().unwrap().mode.clone();
+        if mode == "operator" {
+            // the motion takes the count itself
+            self.run_command_once(name);
+        } else if REPEATED.contains(&name) {
+            let (count, index) = {
+                let window = self.window_tree.find_active_window().unwrap();
+                (window.take_count(), window.buffer_index as usize)
+            };
+            self.buffers[index].begin_group();
+            for _ in 0..count {
+                self.run_command_once(name);
+            }
+            self.buffers[index].end_group();
+        } else {
+            if !PASSES_COUNT.contains(&name) {
+                self.window_tree.find_active_window().unwrap().count = None;
+            }
+            self.run_command_once(name);
+        }
+        self.remember_visual_selection(selection);
+    }
+
+    fn run_command_once(&mut self, name: &str) {
+        let (max_x, max_y) = util::window_area();
+        match name {
+            "focus-west" => { self.window_tree.focus(WEST, max_x, max_y, 0, 0) },
+            "focus-south" => { self.window_tree.focus(SOUTH, max_x, max_y, 0, 0) },
+            "focus-north" => { self.window_tree.focus(NORTH, max_x, max_y, 0, 0) },
+            "focus-east" => { self.window_tree.focus(EAST, max_x, max_y, 0, 0) },
+            "split-west" => { self.split_towards(WEST); },
+            "split-south" => { self.split_towards(SOUTH); },
+            "split-north" => { self.split_towards(NORTH); },
+            "split-east" => { self.split_towards(EAST); },
+            "close-window" => { self.window_tree.find_active_window_tree().unwrap().destroy() },
+            "describe-bindings" => { self.describe_bindings(); },
+            "reload-config" => {
+                self.load_config();
+                if self.message.is_none() {
+                    self.message = Some(format!("Loaded {}", Config::path().display()));
+                }
+            },
+            "record-macro" => {
+                if self.recording.is_some() {
+                    self.stop_recording();
+                } else {
+                    self.window_tree.find_active_window().unwrap().mode = "record_macro".to_string();
+                }
+            },
+            "play-macro" => { self.window_tree.find_active_window().unwrap().mode = "play_macro".to_string(); },
+            "edit-macro" => { self.window_tree.find_active_window().unwrap().mode = "edit_macro".to_string(); },
+            "save" => {
+                let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+                if self.buffers[index].macro_register.is_some() {
+                    self.store_macro(index);
+                } else {
+                    self.write_buffer(index, None);
+                }
+            },
+            "search-forward" => { self.start_search(true); },
+            "search-backward" => { self.start_search(false); },
+            "clear-search-highlight" => { self.search_highlight = false; },
+            "command-line" => { self.start_command_line(); },
+            "execute-command" => { self.start_palette(); },
+            "next-theme" => { self.next_theme(); },
+            "select-register" => {
+                let window = self.window_tree.find_active_window().unwrap();
+                window.register_mode = Some(window.mode.clone());
+                window.mode = "select_register".to_string();
+            },
+            "yank-pop" => { self.yank_pop(); },
+            "repeat-change" => { self.repeat_change(); },
+            "jump-back" => { self.follow_jump_list(true); },
+            "jump-forward" => { self.follow_jump_list(false); },
+            "add-cursor-next-match" => { self.add_cursor_at_next_match(); },
+            "add-cursors-to-lines" => { self.add_cursors_to_lines(); },
+            "add-cursor-above" => { self.add_cursor_vertically(false); },
+            "add-cursor-below" => { self.add_cursor_vertically(true); },
+            "list-registers" => { self.list_registers(); },
+            "normal-mode" => {
+                self.finish_block_insert();
+                let window = self.window_tree.find_active_window().unwrap();
+                // <Escape> in normal mode drops the extra cursors
+                if window.mode == "normal" {
+                    window.cursors.clear();
+                }
+                if window.mode == "insert" {
+                    let position = (window.cursor_y, window.cursor_x);
+                    self.buffers[window.buffer_index as usize].marks.insert('^', position);
+                }
+                if !window.in_visual_mode() {
+                    window.move_left();
+                }
+                window.mode = "normal".to_string();
+                window.mark = None;
+                window.operator = None;
+                window.operator_count = None;
+                window.register = None;
+            },
+            _ if operator::is_operator(name) => { self.start_operator(name); },
+            _ if text_object::is_text_object(name) => { self.select_text_object(name); },
+            _ => {
+                let (mode, visual) = {
+                    let window = self.window_tree.find_active_window().unwrap();
+                    (window.mode.clone(), window.in_visual_mode())
+                };
+                match mode.as_str() {
+                    "operator" => { self.handle_operator(name); },
+                    _ if visual => { self.handle_visual(name); },
+                    _ if cursors::runs_at_each_cursor(name) => {
+                        self.at_each_cursor(|editor| editor.handle_normal(name));
+                    },
+                    _ => { self.handle_normal(name); }
+                }
+            }
+        }
+    }
+
+    pub fn describe_bindings(&mut self) {
+        let mut bindings = self.keymap.bindings.clone();
+        bindings.sort_by(|a, b| (&a.mode, &a.command).cmp(&(&b.mode, &b.command)));
+        let lines = bindings.iter().map(|b| {
+            let description = COMMANDS.iter().find(|&&(n, _)| n == b.command).map(|&(_, d)| d).unwrap_or("");
+            format!("{:10}{:16}{:24}{}", b.mode, keymap::format_keys(&b.keys), b.command, description)
+        }).collect();
+        self.split_towards(SOUTH);
+        self.open_scratch("*bindings*", lines);
+    }
+
+    // Shows `lines` in a buffer that isn't backed by a file, reusing the
+    // buffer if one with the same name is already open.
+    pub fn open_scratch(&mut self, name: &str, lines: Vec<String>) {
+        let path = PathBuf::from(name);
+        let index = match self.buffers.iter().position(|b| b.path == path) {
+            Some(index) => index,
+            None => {
+                self.buffers.push(Buffer::new(path, None, None));
+                self.buffers.len() - 1
+            }
+        };
+        self.buffers[index].lines = lines.iter().map(|line| line.chars().map(|ch| Cell::new(ch, 0)).collect()).collect();
+        if self.buffers[index].lines.len() == 0 {
+            self.buffers[index].lines.push(vec![]);
+        }
+        self.window_tree.find_active_window().unwrap().show_buffer(index as i32);
+    }
+}
@@ -0,0 +1,122 @@
+use std::cmp::min;
+
+use buffer::Buffer;
+
+// Comment markers keyed by syntect syntax name: a line comment, or a block
+// comment's start and end. The syntax definitions this version of syntect
+// loads don't carry them, and other files have none.
+pub static LANGUAGE_COMMENTS: &'static [(&'static str, &'static str)] = &[
+    ("Bourne Again Shell (bash)", "#"),
+    ("C", "//"),
+    ("C#", "//"),
+    ("C++", "//"),
+    ("CSS", "/* */"),
+    ("Clojure", ";"),
+    ("D", "//"),
+    ("Erlang", "%"),
+    ("Go", "//"),
+    ("HTML", "<!-- -->"),
+    ("Haskell", "--"),
+    ("Java", "//"),
+    ("JavaScript", "//"),
+    ("LaTeX", "%"),
+    ("Lisp", ";"),
+    ("Lua", "--"),
+    ("Makefile", "#"),
+    ("Markdown", "<!-- -->"),
+    ("OCaml", "(* *)"),
+    ("Objective-C", "//"),
+    ("PHP", "//"),
+    ("Python", "#"),
+    ("Ruby", "#"),
+    ("Rust", "//"),
+    ("SQL", "--"),
+    ("Scala", "//"),
+    ("Shell-Unix-Generic", "#"),
+    ("TOML", "#"),
+    ("TeX", "%"),
+    ("XML", "<!-- -->"),
+    ("YAML", "#"),
+];
+
+pub fn comment_for(syntax: &str) -> Option<&'static str> {
+    LANGUAGE_COMMENTS.iter().find(|&&(name, _)| name == syntax).map(|&(_, comment)| comment)
+}
+
+// The line comment leader `text` starts with, after any indentation: the
+// marker and any more of its last character or a `!`, so `///` and `//!`
+// for `//`.
+pub fn leader(comment: &str, text: &str) -> Option<String> {
+    let start = match markers(comment) {
+        Some((ref start, ref end)) if end.len() == 0 => start.clone(),
+        _ => { return None; }
+    };
+    let text = text.trim_start();
+    if !text.starts_with(&start) {
+        return None;
+    }
+    let last = start.chars().last().unwrap_or(' ');
+    let extra = text[start.len()..].chars().take_while(|&c| c == last || c == '!').count();
+    Some(text.chars().take(start.chars().count() + extra).collect())
+}
+
+// The start and end of a comment as set in `comment`; the end is empty for
+// line comments.
+fn markers(comment: &str) -> Option<(String, String)> {
+    let mut words = comment.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(start), end) => Some((start.to_string(), end.unwrap_or("").to_string())),
+        (None, _) => None,
+    }
+}
+
+// Comments out lines first to last, or uncomments them if they're all
+// comments already. Markers go in at the indentation of the least indented
+// line so they line up, and blank lines are left alone. False if the
+// buffer has no comment markers.
+pub fn toggle(buffer: &mut Buffer, first: i32, last: i32) -> bool {
+    let (start, end) = match markers(&buffer.settings.comment) {
+        Some(markers) => markers,
+        None => { return false; }
+    };
+    let lines: Vec<i32> = (first..(last + 1)).filter(|&y| buffer.line_text(y).trim().len() > 0).collect();
+    if lines.len() == 0 {
+        return true;
+    }
+    let commented = lines.iter().all(|&y| {
+        let text = buffer.line_text(y);
+        let text = text.trim();
+        text.starts_with(&start) && text.ends_with(&end) && text.len() >= start.len() + end.len()
+    });
+    if commented {
+        for &y in &lines {
+            let text = buffer.line_text(y);
+            if end.len() > 0 {
+                // the end marker and a space before it
+                let trimmed = text.trim_end();
+                let mut x = trimmed.chars().count() - end.chars().count();
+                if trimmed[..trimmed.len() - end.len()].ends_with(' ') {
+                    x -= 1;
+                }
+                let n = text.chars().count() - x;
+                buffer.remove_text(x as i32, y, n, true);
+            }
+            // the start marker and a space after it
+            let x = buffer.first_non_blank(y);
+            let rest: String = text.chars().skip(x as usize + start.chars().count()).collect();
+            let n = start.chars().count() + if rest.starts_with(' ') { 1 } else { 0 };
+            buffer.remove_text(x, y, n, true);
+        }
+    } else {
+        let column = lines.iter().map(|&y| buffer.display_col(buffer.first_non_blank(y), y)).min().unwrap_or(0);
+        for &y in &lines {
+            if end.len() > 0 {
+                let length = buffer.lines[y as usize].len() as i32;
+                buffer.insert(&format!(" {}", end), length, y, true);
+            }
+            let x = min(buffer.x_at_col(column, y), buffer.first_non_blank(y));
+            buffer.insert(&format!("{} ", start), x, y, true);
+        }
+    }
+    true
+}
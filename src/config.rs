@@ -0,0 +1,335 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use toml;
+
+use clipboard;
+use command;
+use comment;
+use indent;
+use pairs;
+use util;
+
+// Settings that can differ per filetype.
+#[derive(Clone)]
+pub struct Settings {
+    pub tab_width: i32,
+    // columns per level of indentation; 0 uses tab_width
+    pub shift_width: i32,
+    pub expand_tab: bool,
+    // typing the opener of one of `pairs`, each an opener then its closer,
+    // adds the closer too
+    pub auto_pairs: bool,
+    pub pairs: String,
+    // new lines start with the indentation of the one before, a level
+    // deeper after one of `indent_after`
+    pub auto_indent: bool,
+    pub indent_after: String,
+    // a line comment marker, or a block comment's start and end separated
+    // by a space; empty where there are no comments
+    pub comment: String,
+    // the column gq fills lines to, and typing past it breaks the line
+    // when auto_wrap is on
+    pub text_width: i32,
+    pub auto_wrap: bool,
+    // characters besides letters and digits that make up words
+    pub word_chars: String,
+}
+
+// The names `Settings::set` understands.
+pub static SETTINGS: &'static [&'static str] = &[
+    "auto_indent",
+    "auto_pairs",
+    "auto_wrap",
+    "comment",
+    "expand_tab",
+    "indent_after",
+    "pairs",
+    "shift_width",
+    "tab_width",
+    "text_width",
+    "word_chars",
+];
+
+pub struct Config {
+    pub theme: String,
+    pub border_color: i16,
+    pub selection_fg: i16,
+    pub selection_bg: i16,
+    pub search_fg: i16,
+    pub search_bg: i16,
+    // one of clipboard::PROVIDERS
+    pub clipboard: String,
+    pub clipboard_copy: String,
+    pub clipboard_paste: String,
+    pub settings: Settings,
+    // keyed by syntect syntax name, e.g. "Rust" or "Python"
+    pub filetypes: BTreeMap<String, toml::Table>,
+    // (mode, keys, command)
+    pub keys: Vec<(String, String, String)>,
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            tab_width: 4,
+            shift_width: 0,
+            expand_tab: true,
+            auto_pairs: true,
+            pairs: "()[]{}\"\"''".to_string(),
+            auto_indent: true,
+            indent_after: "{([".to_string(),
+            comment: "".to_string(),
+            text_width: 80,
+            auto_wrap: false,
+            word_chars: "_".to_string(),
+        }
+    }
+
+    pub fn shift_width(&self) -> i32 {
+        if self.shift_width > 0 { self.shift_width } else { self.tab_width }
+    }
+
+    // Returns false if `key` isn't a setting at all.
+    pub fn set(&mut self, key: &str, value: &toml::Value, errors: &mut Vec<String>) -> bool {
+        match key {
+            "tab_width" => {
+                match value.as_integer() {
+                    Some(n) if n > 0 => { self.tab_width = n as i32; },
+                    _ => { errors.push(format!("{} must be a positive integer", key)); }
+                }
+            },
+            "shift_width" => {
+                match value.as_integer() {
+                    Some(n) if n >= 0 => { self.shift_width = n as i32; },
+                    _ => { errors.push(format!("{} must be 0 or a positive integer", key)); }
+                }
+            },
+            "text_width" => {
+                match value.as_integer() {
+                    Some(n) if n > 0 => { self.text_width = n as i32; },
+                    _ => { errors.push(format!("{} must be a positive integer", key)); }
+                }
+            },
+            "auto_wrap" => { set_bool(&mut self.auto_wrap, key, value, errors); },
+            "expand_tab" => { set_bool(&mut self.expand_tab, key, value, errors); },
+            "auto_pairs" => { set_bool(&mut self.auto_pairs, key, value, errors); },
+            "pairs" => {
+                match value.as_str() {
+                    Some(s) if s.chars().count() % 2 == 0 => { self.pairs = s.to_string(); },
+                    _ => { errors.push(format!("{} must be a string of openers each followed by its closer", key)); }
+                }
+            },
+            "auto_indent" => { set_bool(&mut self.auto_indent, key, value, errors); },
+            "indent_after" => { set_string(&mut self.indent_after, key, value, errors); },
+            "comment" => {
+                match value.as_str() {
+                    Some(s) if s.split_whitespace().count() <= 2 => { self.comment = s.to_string(); },
+                    _ => { errors.push(format!("{} must be a line comment marker, a block comment's start and end, or empty", key)); }
+                }
+            },
+            "word_chars" => { set_string(&mut self.word_chars, key, value, errors); },
+            _ => { return false; }
+        }
+        true
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        match key {
+            "tab_width" => Some(self.tab_width.to_string()),
+            "shift_width" => Some(self.shift_width.to_string()),
+            "text_width" => Some(self.text_width.to_string()),
+            "auto_wrap" => Some(self.auto_wrap.to_string()),
+            "expand_tab" => Some(self.expand_tab.to_string()),
+            "auto_pairs" => Some(self.auto_pairs.to_string()),
+            "pairs" => Some(self.pairs.clone()),
+            "auto_indent" => Some(self.auto_indent.to_string()),
+            "indent_after" => Some(self.indent_after.clone()),
+            "comment" => Some(self.comment.clone()),
+            "word_chars" => Some(self.word_chars.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn apply(&mut self, table: &toml::Table, errors: &mut Vec<String>) {
+        for (key, value) in table {
+            if !self.set(key, value, errors) {
+                errors.push(format!("unknown setting `{}`", key));
+            }
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            theme: "base16-ocean.dark".to_string(),
+            border_color: 3,
+            selection_fg: 0,
+            selection_bg: 10,
+            search_fg: 0,
+            search_bg: 11,
+            clipboard: "auto".to_string(),
+            clipboard_copy: String::new(),
+            clipboard_paste: String::new(),
+            settings: Settings::new(),
+            filetypes: BTreeMap::new(),
+            keys: vec![],
+        }
+    }
+
+    pub fn path() -> PathBuf {
+        util::config_dir().join("config.toml")
+    }
+
+    // Reads `path` over the defaults. A missing file isn't an error; anything
+    // that can't be used is skipped and described in the returned errors.
+    pub fn load(path: &Path) -> (Config, Vec<String>) {
+        let mut config = Config::new();
+        let mut errors = vec![];
+        let mut s = String::new();
+        match File::open(path) {
+            Ok(mut f) => {
+                if let Err(e) = f.read_to_string(&mut s) {
+                    errors.push(format!("{}: {}", path.display(), e));
+                    return (config, errors);
+                }
+            },
+            Err(_) => { return (config, errors); }
+        }
+
+        let mut parser = toml::Parser::new(&s);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                for e in &parser.errors {
+                    let (line, col) = parser.to_linecol(e.lo);
+                    errors.push(format!("{}:{}:{}: {}", path.display(), line + 1, col + 1, e.desc));
+                }
+                return (config, errors);
+            }
+        };
+
+        let mut problems = vec![];
+        for (key, value) in &table {
+            match key.as_str() {
+                "theme" => { set_string(&mut config.theme, key, value, &mut problems); },
+                "clipboard" => {
+                    set_string(&mut config.clipboard, key, value, &mut problems);
+                    if !clipboard::PROVIDERS.contains(&config.clipboard.as_str()) {
+                        problems.push(format!("clipboard must be one of {}", clipboard::PROVIDERS.join(", ")));
+                        config.clipboard = "auto".to_string();
+                    }
+                },
+                "clipboard_copy" => { set_string(&mut config.clipboard_copy, key, value, &mut problems); },
+                "clipboard_paste" => { set_string(&mut config.clipboard_paste, key, value, &mut problems); },
+                "colors" => {
+                    match value.as_table() {
+                        Some(colors) => {
+                            for (name, color) in colors {
+                                match name.as_str() {
+                                    "border" => { set_color(&mut config.border_color, name, color, &mut problems); },
+                                    "selection_fg" => { set_color(&mut config.selection_fg, name, color, &mut problems); },
+                                    "selection_bg" => { set_color(&mut config.selection_bg, name, color, &mut problems); },
+                                    "search_fg" => { set_color(&mut config.search_fg, name, color, &mut problems); },
+                                    "search_bg" => { set_color(&mut config.search_bg, name, color, &mut problems); },
+                                    _ => { problems.push(format!("unknown color `{}`", name)); }
+                                }
+                            }
+                        },
+                        None => { problems.push("colors must be a table".to_string()); }
+                    }
+                },
+                "filetype" => {
+                    match value.as_table() {
+                        Some(filetypes) => {
+                            for (name, settings) in filetypes {
+                                match settings.as_table() {
+                                    Some(settings) => {
+                                        // check it now rather than every time a file is opened
+                                        Settings::new().apply(settings, &mut problems);
+                                        config.filetypes.insert(name.clone(), settings.clone());
+                                    },
+                                    None => { problems.push(format!("filetype.{} must be a table", name)); }
+                                }
+                            }
+                        },
+                        None => { problems.push("filetype must be a table".to_string()); }
+                    }
+                },
+                "keys" => {
+                    match value.as_table() {
+                        Some(modes) => {
+                            for (mode, bindings) in modes {
+                                let bindings = match bindings.as_table() {
+                                    Some(bindings) => bindings,
+                                    None => {
+                                        problems.push(format!("keys.{} must be a table", mode));
+                                        continue;
+                                    }
+                                };
+                                for (keys, command) in bindings {
+                                    match command.as_str() {
+                                        Some(c) if c == "unbound" || command::is_command(c) => {
+                                            config.keys.push((mode.clone(), keys.clone(), c.to_string()));
+                                        },
+                                        _ => { problems.push(format!("keys.{}: `{}` isn't bound to a command", mode, keys)); }
+                                    }
+                                }
+                            }
+                        },
+                        None => { problems.push("keys must be a table".to_string()); }
+                    }
+                },
+                _ => {
+                    if !config.settings.set(key, value, &mut problems) {
+                        problems.push(format!("unknown setting `{}`", key));
+                    }
+                }
+            }
+        }
+        for problem in problems {
+            errors.push(format!("{}: {}", path.display(), problem));
+        }
+        (config, errors)
+    }
+
+    pub fn settings_for(&self, syntax: Option<&str>) -> Settings {
+        let mut settings = self.settings.clone();
+        if let Some(openers) = syntax.and_then(indent::openers_for) {
+            settings.indent_after = openers.to_string();
+        }
+        if let Some(pairs) = syntax.and_then(pairs::pairs_for) {
+            settings.pairs = pairs.to_string();
+        }
+        if let Some(comment) = syntax.and_then(comment::comment_for) {
+            settings.comment = comment.to_string();
+        }
+        if let Some(table) = syntax.and_then(|name| self.filetypes.get(name)) {
+            settings.apply(table, &mut vec![]);
+        }
+        settings
+    }
+}
+
+fn set_bool(setting: &mut bool, key: &str, value: &toml::Value, errors: &mut Vec<String>) {
+    match value.as_bool() {
+        Some(b) => { *setting = b; },
+        None => { errors.push(format!("{} must be true or false", key)); }
+    }
+}
+
+fn set_string(setting: &mut String, key: &str, value: &toml::Value, errors: &mut Vec<String>) {
+    match value.as_str() {
+        Some(s) => { *setting = s.to_string(); },
+        None => { errors.push(format!("{} must be a string", key)); }
+    }
+}
+
+fn set_color(setting: &mut i16, key: &str, value: &toml::Value, errors: &mut Vec<String>) {
+    match value.as_integer() {
+        Some(n) if n >= -1 && n < 256 => { *setting = n as i16; },
+        _ => { errors.push(format!("{} must be a color number between -1 and 255", key)); }
+    }
+}
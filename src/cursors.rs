@@ -0,0 +1,216 @@
+use std::cmp::{min, max};
+
+use regex;
+use regex::Regex;
+
+use editor::Editor;
+use motion;
+use search;
+use util;
+
+// Commands that run at every cursor when there are several. Everything
+// typed in insert mode does too.
+pub static MULTI_CURSOR_COMMANDS: &'static [&'static str] = &[
+    "append",
+    "append-eol",
+    "decrement-number",
+    "dedent-line",
+    "delete-char",
+    "increment-number",
+    "indent-line",
+    "insert-bol",
+    "move-bol",
+    "move-down",
+    "move-eol",
+    "move-left",
+    "move-right",
+    "move-up",
+    "switch-case",
+];
+
+pub fn runs_at_each_cursor(command: &str) -> bool {
+    MULTI_CURSOR_COMMANDS.contains(&command) || motion::CURSOR_MOTIONS.contains(&command)
+}
+
+impl Editor {
+    // Runs `action` with the active window's cursor at each of its cursors
+    // in turn, last in the buffer first, as one undo step. Cursors after the
+    // one being acted on move along with the text.
+    pub fn at_each_cursor<F>(&mut self, mut action: F) where F: FnMut(&mut Editor) {
+        let (mut positions, primary, count, index) = {
+            let window = self.window_tree.find_active_window().unwrap();
+            if window.cursors.len() == 0 {
+                action(self);
+                return;
+            }
+            let primary = (window.cursor_y, window.cursor_x);
+            let mut positions = window.cursors.clone();
+            positions.push(primary);
+            positions.sort();
+            positions.dedup();
+            positions.reverse();
+            (positions, primary, window.count, window.buffer_index as usize)
+        };
+        let eof = self.buffers[index].eof();
+        positions.retain(|&(y, _)| y < eof);
+        self.buffers[index].begin_group();
+        // where each cursor ended up, and whether it's the primary one
+        let mut done: Vec<((i32, i32), bool)> = vec![];
+        for (y, x) in positions {
+            let (eof, length) = {
+                let window = self.window_tree.find_active_window().unwrap();
+                let ref buffer = self.buffers[index];
+                window.cursor_y = y;
+                window.cursor_x = min(x, buffer.lines[y as usize].len() as i32);
+                window.col = window.cursor_x;
+                window.row = y;
+                window.count = count;
+                (buffer.eof(), buffer.lines[y as usize].len() as i32)
+            };
+            action(self);
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref buffer = self.buffers[index];
+            let moved = (window.cursor_y, window.cursor_x);
+            let added_lines = buffer.eof() - eof;
+            for &mut (ref mut position, _) in done.iter_mut() {
+                if added_lines != 0 && position.0 == y {
+                    // the rest of the line went wherever the cursor did
+                    *position = (moved.0, moved.1 + position.1 - x);
+                } else if position.0 > y {
+                    position.0 += added_lines;
+                } else if position.0 == y {
+                    position.1 += buffer.lines[y as usize].len() as i32 - length;
+                }
+            }
+            done.push((moved, (y, x) == primary));
+        }
+        self.buffers[index].end_group();
+
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let primary = done.iter().find(|&&(_, is_primary)| is_primary).map(|&(position, _)| position);
+        if let Some((y, x)) = primary {
+            window.cursor_y = y;
+            window.cursor_x = x;
+            window.col = x;
+            window.row = y;
+        }
+        // cursors that have run into each other become one
+        let mut cursors: Vec<(i32, i32)> = done.iter()
+            .filter(|&&(_, is_primary)| !is_primary)
+            .map(|&(position, _)| position)
+            .filter(|&position| Some(position) != primary)
+            .collect();
+        cursors.sort();
+        cursors.dedup();
+        window.cursors = cursors;
+        window.scroll_to_cursor(window_height);
+    }
+
+    // Adds a cursor at the next occurrence of the word under the cursor, or
+    // of the text selected on one line, and moves there.
+    pub fn add_cursor_at_next_match(&mut self) {
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        let y = window.cursor_y;
+        let (pattern, start, offset) = match window.mark {
+            Some(mark) if window.mode == "visual" && mark.0 == y => {
+                let start = min(mark.1, window.cursor_x);
+                let end = min(max(mark.1, window.cursor_x) + 1, buffer.lines[y as usize].len() as i32);
+                (regex::quote(&buffer.text_between((start, y), (end, y))), start, 0)
+            },
+            _ => {
+                match search::word_under(buffer, window.cursor_x, y) {
+                    Some((start, word)) => (format!(r"\b{}\b", regex::quote(&word)), start, window.cursor_x - start),
+                    None => {
+                        self.message = Some("No word under the cursor".to_string());
+                        return;
+                    }
+                }
+            }
+        };
+        let re = match Regex::new(&pattern) {
+            Ok(re) if pattern.len() > 0 => re,
+            _ => { return; }
+        };
+        let here = (y, start + offset);
+        let mut from = (start, y);
+        for _ in 0..(window.cursors.len() + 1) {
+            let (x, match_y) = match search::find_match(buffer, &re, from.0, from.1, true) {
+                Some((position, _)) => position,
+                None => { break; }
+            };
+            let there = (match_y, x + offset);
+            if there == here {
+                break;
+            }
+            if window.cursors.contains(&there) {
+                from = (x, match_y);
+                continue;
+            }
+            window.cursors.push(here);
+            window.cursor_y = there.0;
+            window.cursor_x = there.1;
+            window.col = there.1;
+            window.row = there.0;
+            window.mark = None;
+            window.mode = "normal".to_string();
+            window.scroll_to_cursor(window_height);
+            return;
+        }
+        self.message = Some("No more matches".to_string());
+    }
+
+    // A cursor on each line of the visual selection, in the cursor's column
+    // or the left edge of a block.
+    pub fn add_cursors_to_lines(&mut self) {
+        let block = self.visual_block();
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        let mark = match window.mark.take() {
+            Some(mark) => mark,
+            None => { return; }
+        };
+        let column = match block {
+            Some(block) if window.mode == "visual_block" => block.left,
+            _ => buffer.display_col(window.cursor_x, window.cursor_y),
+        };
+        for y in min(mark.0, window.cursor_y)..(max(mark.0, window.cursor_y) + 1) {
+            let position = (y, min(buffer.x_at_col(column, y), buffer.eol(y)));
+            if y == window.cursor_y {
+                window.cursor_x = position.1;
+                window.col = position.1;
+            } else if !window.cursors.contains(&position) {
+                window.cursors.push(position);
+            }
+        }
+        window.mode = "normal".to_string();
+    }
+
+    // Leaves a cursor where the cursor is and moves to the line above or
+    // below, in the same column.
+    pub fn add_cursor_vertically(&mut self, down: bool) {
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        let y = window.cursor_y + if down { 1 } else { -1 };
+        if y < 0 || y >= buffer.eof() {
+            return;
+        }
+        let here = (window.cursor_y, window.cursor_x);
+        if !window.cursors.contains(&here) {
+            window.cursors.push(here);
+        }
+        let column = buffer.display_col(window.cursor_x, window.cursor_y);
+        window.cursor_y = y;
+        window.cursor_x = min(buffer.x_at_col(column, y), buffer.eol(y));
+        window.row = y;
+        let there = (window.cursor_y, window.cursor_x);
+        window.cursors.retain(|&position| position != there);
+        window.scroll_to_cursor(window_height);
+    }
+}
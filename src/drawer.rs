@@ -6,9 +6,13 @@ use ncurses::*;
 use fuzzyrusty::fuzz;
 
 pub struct Drawer {
+    // "find_files", or the mode a plain prompt is for
+    pub kind: String,
     pub prompt: String,
     pub value: String,
     pub lines: Vec<String>,
+    // everything a picker can show, of which `lines` are the matches
+    pub choices: Vec<String>,
     pub active_line_index: i32,
     pub scroll_index: i32,
 }
@@ -23,15 +27,67 @@ impl Drawer {
         let paths: Vec<String> = fs::read_dir(dir.clone()).unwrap().map(|res| res.unwrap().file_name().to_string_lossy().into_owned()).collect();
         
         Drawer{
+            kind: "find_files".to_string(),
             prompt: "Find files: ".to_string(),
             value: dir.to_str().unwrap().to_string() + "/",
             lines: paths,
+            choices: vec![],
             active_line_index: 0,
             scroll_index: 0,
         }
     }
 
+    // A prompt on the bottom line without a list.
+    pub fn new_prompt(kind: &str, prompt: &str) -> Drawer {
+        Drawer{
+            kind: kind.to_string(),
+            prompt: prompt.to_string(),
+            value: String::new(),
+            lines: vec![],
+            choices: vec![],
+            active_line_index: 0,
+            scroll_index: 0,
+        }
+    }
+
+    // A prompt that narrows `choices` down as it's typed.
+    pub fn new_picker(kind: &str, prompt: &str, choices: Vec<String>) -> Drawer {
+        Drawer{
+            kind: kind.to_string(),
+            prompt: prompt.to_string(),
+            value: String::new(),
+            lines: choices.clone(),
+            choices: choices,
+            active_line_index: 0,
+            scroll_index: 0,
+        }
+    }
+
+    // Keeps the choices that fuzzily match the value, best first.
+    fn filter_choices(&mut self) {
+        if self.value.len() == 0 {
+            self.lines = self.choices.clone();
+        } else {
+            let value = self.value.to_lowercase();
+            let mut scored: Vec<(u8, &String)> = self.choices.iter()
+                .map(|choice| (fuzz::partial_ratio(&value, &choice.to_lowercase()), choice))
+                .filter(|&(score, _)| score >= 70)
+                .collect();
+            // sort_by is stable, so equal scores keep their order
+            scored.sort_by(|a, b| b.0.cmp(&a.0));
+            self.lines = scored.into_iter().map(|(_, choice)| choice.clone()).collect();
+        }
+        self.active_line_index = 0;
+        self.scroll_index = 0;
+    }
+
     pub fn draw(&self, max_x: i32, max_y: i32) {
+        if self.kind != "find_files" && self.lines.len() == 0 {
+            mv(max_y - 1, 0);
+            addstr(format!("{}{}", self.prompt, self.value).as_str());
+            clrtoeol();
+            return;
+        }
         let height = min(self.lines.len(), ((max_y / 2) - 3) as usize);
         let top_border = (0..max_x).map(|_| "-").collect::<String>();
         let mut y = max_y - height as i32 - 3;
@@ -121,6 +177,18 @@ impl Drawer {
     }
 
     pub fn handle_key(&mut self, key: &str) {
+        if self.kind != "find_files" {
+            match key {
+                "<Backspace>" | "<DEL>" => { self.value.pop(); },
+                // other named keys don't type anything
+                _ if key.len() > 1 && key.starts_with("<") => { return; },
+                _ => { self.value.push_str(key); }
+            }
+            if self.choices.len() > 0 {
+                self.filter_choices();
+            }
+            return;
+        }
         match key {
             "<Backspace>" | "<DEL>" => {
                 if self.value.len() != 0 {
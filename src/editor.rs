@@ -6,22 +6,86 @@ use std::path::PathBuf;
 use syntect::parsing::SyntaxSet;
 use syntect::highlighting::ThemeSet;
 use std::path::Path;
+use std::mem;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 use buffer::Buffer;
 use window::Window;
 use window_tree::WindowTree;
 use cell::Cell;
 use drawer::Drawer;
+use keymap::Keymap;
+use config::Config;
+use macros;
+use marks;
+use search::{Search, SearchOrigin};
+use substitute::Substitution;
+use register::{Registers, Paste};
+use block::{Block, BlockInsert};
+use clipboard::Clipboard;
+use util;
 
-const NORTH: usize = 1;
-const SOUTH: usize = 2;
-const EAST: usize = 3;
-const WEST: usize = 4;
+static COLOR_PAIR_DEFAULT: i16 = 1;
+static COLOR_PAIR_HIGHLIGHT: i16 = 2;
+static COLOR_PAIR_SEARCH: i16 = 3;
+
+pub const NORTH: usize = 1;
+pub const SOUTH: usize = 2;
+pub const EAST: usize = 3;
+pub const WEST: usize = 4;
+
+// How long to wait for the rest of a key sequence, in milliseconds.
+const KEY_TIMEOUT: u64 = 1000;
+
+const MAX_COUNT: i32 = 99999;
 
 pub struct Editor {
     pub buffers: Vec<Buffer>,
     pub window_tree: WindowTree,
     pub drawer: Option<Drawer>,
+    pub keymap: Keymap,
+    pub pending_keys: Vec<String>,
+    pub pending_since: Option<Instant>,
+    pub config: Config,
+    pub message: Option<String>,
+    pub macros: HashMap<char, Vec<String>>,
+    pub recording: Option<(char, Vec<String>)>,
+    pub last_macro: Option<char>,
+    pub macro_depth: usize,
+    pub search: Option<Search>,
+    pub search_highlight: bool,
+    pub search_origin: Option<SearchOrigin>,
+    // a :s waiting on y/n for each match
+    pub substitution: Option<Substitution>,
+    // whether the command line's preview is applied to the active buffer
+    pub command_preview: bool,
+    pub command_history: Vec<String>,
+    pub registers: Registers,
+    pub last_paste: Option<Paste>,
+    // the mode M-x was typed in, to run the picked command in
+    pub palette_mode: Option<String>,
+    // what R has typed over, for backspace to put back; None past the end
+    // of the line
+    pub overtyped: Vec<Option<char>>,
+    // the visual mode S was typed in, until the pair to wrap with is typed
+    pub wrap_mode: Option<String>,
+    // the history entry shown, and what had been typed before going back
+    pub history_position: Option<(usize, String)>,
+    // I or A typed on a block, and the block :string-rectangle will fill
+    pub block_insert: Option<BlockInsert>,
+    pub rectangle: Option<Block>,
+    // the keys of the command being typed, with the buffer and undo group
+    // it started at, and those of the last one that changed something;
+    // each key says whether it was part of a count
+    pub change_keys: Vec<(String, bool)>,
+    pub change_start: Option<(usize, usize)>,
+    pub last_change: Vec<(String, bool)>,
+    // whether `.` is typing the last change again
+    pub repeating: bool,
+    // marks A to Z, kept between sessions
+    pub file_marks: HashMap<char, (PathBuf, i32, i32)>,
 }
 
 
@@ -30,45 +94,213 @@ impl Editor {
         let mut window_tree = WindowTree::new(None);
         window_tree.leaf = Window::new();
         window_tree.leaf.active = true;
-        Editor {
+        let mut editor = Editor {
             buffers: vec![],
             window_tree: window_tree,
             drawer: None,
+            keymap: Keymap::new(),
+            pending_keys: vec![],
+            pending_since: None,
+            config: Config::new(),
+            message: None,
+            macros: macros::load(),
+            recording: None,
+            last_macro: None,
+            macro_depth: 0,
+            search: None,
+            search_highlight: false,
+            search_origin: None,
+            substitution: None,
+            command_preview: false,
+            command_history: vec![],
+            registers: Registers::new(),
+            last_paste: None,
+            palette_mode: None,
+            overtyped: vec![],
+            wrap_mode: None,
+            history_position: None,
+            block_insert: None,
+            rectangle: None,
+            change_keys: vec![],
+            change_start: None,
+            last_change: vec![],
+            repeating: false,
+            file_marks: marks::load(),
+        };
+        editor.load_config();
+        editor
+    }
+
+    // (Re)reads the config and keymap files and applies them to everything
+    // that's already open. Problems end up on the message line.
+    pub fn load_config(&mut self) {
+        let (mut config, mut errors) = Config::load(&Config::path());
+        if !ThemeSet::load_defaults().themes.contains_key(&config.theme) {
+            errors.push(format!("unknown theme `{}`", config.theme));
+            config.theme = Config::new().theme;
+        }
+
+        let mut keymap = Keymap::new();
+        errors.append(&mut keymap.load(&util::config_dir().join("keymap")));
+        for &(ref mode, ref keys, ref command) in &config.keys {
+            keymap.bind(mode, keys, command);
+        }
+
+        init_pair(COLOR_PAIR_HIGHLIGHT, config.selection_fg, config.selection_bg);
+        init_pair(COLOR_PAIR_DEFAULT, config.border_color, -1);
+        init_pair(COLOR_PAIR_SEARCH, config.search_fg, config.search_bg);
+
+        for buffer in &mut self.buffers {
+            buffer.theme = config.theme.clone();
+            buffer.settings = config.settings_for(buffer.syntax_name());
+            buffer.highlight_all();
+        }
+
+        self.registers.clipboard = Clipboard::new(&config.clipboard, &config.clipboard_copy, &config.clipboard_paste);
+        self.config = config;
+        self.keymap = keymap;
+        self.message = match errors.len() {
+            0 => None,
+            1 => Some(errors[0].clone()),
+            n => Some(format!("{} (and {} more)", errors[0], n - 1)),
+        };
+    }
+
+    // Switches every buffer to the next of the built-in themes.
+    pub fn next_theme(&mut self) {
+        let themes: Vec<String> = ThemeSet::load_defaults().themes.keys().cloned().collect();
+        if themes.len() == 0 {
+            return;
+        }
+        let index = themes.iter().position(|t| *t == self.config.theme).map(|i| (i + 1) % themes.len()).unwrap_or(0);
+        self.config.theme = themes[index].clone();
+        for buffer in &mut self.buffers {
+            buffer.theme = self.config.theme.clone();
+            buffer.highlight_all();
         }
+        self.message = Some(format!("Theme: {}", self.config.theme));
     }
 
     pub fn handle_input(&mut self, key: &str) {
-        let mut max_y = 0;
-        let mut max_x = 0;
-        getmaxyx(stdscr(), &mut max_y, &mut max_x);
-        match key {
-            "<M-h>" => { self.window_tree.focus(WEST, max_x, max_y, 0, 0) },
-            "<M-j>" => { self.window_tree.focus(SOUTH, max_x, max_y, 0, 0) },
-            "<M-k>" => { self.window_tree.focus(NORTH, max_x, max_y, 0, 0) },
-            "<M-l>" => { self.window_tree.focus(EAST, max_x, max_y, 0, 0) },
-            "<M-H>" => { self.split_towards(WEST); },
-            "<M-J>" => { self.split_towards(SOUTH); },
-            "<M-K>" => { self.split_towards(NORTH); },
-            "<M-L>" => { self.split_towards(EAST); },
-            "<C-q>" => { self.window_tree.find_active_window_tree().unwrap().destroy() },
-            _ => {
-                let ref mode = self.window_tree.find_active_window().unwrap().mode.clone();
-                match mode.as_str() {
-                    "normal" => { self.handle_normal(key); },
-                    "delete" => { self.handle_delete(key); },
-                    "insert" => { self.handle_insert(key); },
-                    "find_char" => { self.handle_find_char(key); },
-                    "find_char_backwards" => { self.handle_find_char_backwards(key); },
-                    "replace" => { self.handle_replace(key); },
-                    "execute" => { self.handle_execute(key); },
-                    "find_files" => { self.handle_find_files(key); },
-                    "visual" => { self.handle_visual(key); },
-                    _ => ()
-                }
+        self.message = None;
+        if let Some((_, ref mut keys)) = self.recording {
+            keys.push(key.to_string());
+        }
+        self.start_change_key(key);
+        self.process_key(key);
+        self.end_change_key();
+    }
+
+    // Everything but macro recording, so macros can replay keys through here.
+    pub fn process_key(&mut self, key: &str) {
+        let (mode, visual) = {
+            let window = self.window_tree.find_active_window().unwrap();
+            (window.mode.clone(), window.in_visual_mode())
+        };
+        if self.pending_keys.len() == 0 && (mode == "normal" || visual || mode == "operator") {
+            if self.accumulate_count(key) {
+                self.count_change_key();
+                return;
+            }
+        }
+        self.pending_keys.push(key.to_string());
+        let (command, is_prefix) = self.keymap.lookup(&mode, &self.pending_keys);
+        if is_prefix {
+            // wait for the rest of the sequence, or the timeout
+            self.pending_since = Some(Instant::now());
+            return;
+        }
+        let keys = mem::replace(&mut self.pending_keys, vec![]);
+        self.pending_since = None;
+        match command {
+            Some(command) => { self.run_command(&command); },
+            None => { self.resolve_keys(keys); }
+        }
+    }
+
+    // Digits typed before a command build up its count. A leading 0 is
+    // still a command.
+    fn accumulate_count(&mut self, key: &str) -> bool {
+        let window = self.window_tree.find_active_window().unwrap();
+        let digit = match key.parse::<i32>() {
+            Ok(digit) if key.len() == 1 => digit,
+            _ => { return false; }
+        };
+        if digit == 0 && window.count.is_none() {
+            return false;
+        }
+        window.count = Some(min(MAX_COUNT, window.count.unwrap_or(0) * 10 + digit));
+        true
+    }
+
+    pub fn handle_timeout(&mut self) {
+        if let Some(since) = self.pending_since {
+            if since.elapsed() >= Duration::from_millis(KEY_TIMEOUT) {
+                self.flush_pending_keys();
+                self.end_change_key();
+            }
+        }
+    }
+
+    // Acts on keys waiting for the rest of a sequence without waiting.
+    pub fn flush_pending_keys(&mut self) {
+        if self.pending_keys.len() > 0 {
+            let keys = mem::replace(&mut self.pending_keys, vec![]);
+            self.pending_since = None;
+            self.resolve_keys(keys);
+        }
+    }
+
+    // Runs the longest bound prefix of `keys` (or passes the first key on to
+    // the mode) and feeds the remaining keys back through `handle_input`.
+    fn resolve_keys(&mut self, keys: Vec<String>) {
+        let mode = self.window_tree.find_active_window().unwrap().mode.clone();
+        let mut consumed = 1;
+        let mut command = None;
+        for n in (1..(keys.len() + 1)).rev() {
+            if let (Some(c), _) = self.keymap.lookup(&mode, &keys[..n]) {
+                command = Some(c);
+                consumed = n;
+                break;
             }
         }
+        match command {
+            Some(command) => { self.run_command(&command); },
+            None => { self.handle_key(&keys[0]); }
+        }
+        for key in keys.iter().skip(consumed) {
+            self.process_key(key);
+        }
     }
 
+    // Keys that aren't bound to a command in the current mode.
+    fn handle_key(&mut self, key: &str) {
+        let ref mode = self.window_tree.find_active_window().unwrap().mode.clone();
+        match mode.as_str() {
+            "insert" => { self.at_each_cursor(|editor| editor.handle_insert(key)); },
+            "find_char" => { self.handle_find_char(key, true, false); },
+            "find_char_backwards" => { self.handle_find_char(key, false, false); },
+            "till_char" => { self.handle_find_char(key, true, true); },
+            "till_char_backwards" => { self.handle_find_char(key, false, true); },
+            "replace" => { self.handle_replace(key); },
+            "overtype" => { self.handle_overtype(key); },
+            "set_mark" => { self.handle_set_mark(key); },
+            "goto_mark" => { self.handle_goto_mark(key, false); },
+            "goto_mark_line" => { self.handle_goto_mark(key, true); },
+            "select_register" => { self.handle_select_register(key); },
+            "find_files" => { self.handle_find_files(key); },
+            "search" => { self.handle_search(key); },
+            "command" => { self.handle_command_line(key); },
+            "palette" => { self.handle_palette(key); },
+            "substitute_confirm" => { self.handle_substitute_confirm(key); },
+            "string_rectangle" => { self.handle_string_rectangle(key); },
+            "wrap_selection" => { self.handle_wrap_selection(key); },
+            "record_macro" => { self.handle_record_macro(key); },
+            "play_macro" => { self.handle_play_macro(key); },
+            "edit_macro" => { self.handle_edit_macro(key); },
+            _ => ()
+        }
+    }
 
     pub fn open(&mut self, path: PathBuf) {
         match File::open(&path) {
@@ -87,6 +319,8 @@ impl Editor {
                     },
                     None => ()
                 };
+                buf.theme = self.config.theme.clone();
+                buf.settings = self.config.settings_for(buf.syntax_name());
                 for (index, line) in reader.lines().enumerate() {
                     buf.lines.push(vec![]);
                     let ln = line.unwrap();
@@ -104,26 +338,37 @@ impl Editor {
     }
 
     pub fn draw(&mut self) {
-        let mut max_y = 0;
-        let mut max_x = 0;
-        getmaxyx(stdscr(), &mut max_y, &mut max_x);
-        self.window_tree.draw(&self.buffers, max_x, max_y, 0, 0);
+        let (max_x, max_y) = util::window_area();
+
+        // message line
+        mv(max_y, 0);
+        if let Some(ref message) = self.message {
+            addstr(message.as_str());
+        } else if let Some((register, _)) = self.recording {
+            addstr(format!("recording @{}", register).as_str());
+        }
+        clrtoeol();
+        wnoutrefresh(stdscr());
+
+        let pattern = self.highlight_pattern();
+        self.window_tree.draw(&self.buffers, pattern.as_ref(), max_x, max_y, 0, 0);
         let ref active = self.window_tree.find_active_window().unwrap();
 
         match active.mode.clone().as_str() {
-            "find_files" => {
-                self.drawer.as_ref().unwrap().draw(max_x, max_y);
+            "find_files" | "search" | "command" | "palette" | "string_rectangle" => {
+                self.drawer.as_ref().unwrap().draw(max_x, max_y + 1);
                 refresh();
             },
             _ => {
-                wmove(active.pane, active.cursor_y - active.scroll_y + 1, active.cursor_x + 1);
+                let x = self.buffers[active.buffer_index as usize].display_col(active.cursor_x, active.cursor_y);
+                wmove(active.pane, active.cursor_y - active.scroll_y + 1, x + 1);
                 wnoutrefresh(active.pane);
                 doupdate();
             }
         }
     }
 
-    fn split_towards(&mut self, direction: usize) {
+    pub fn split_towards(&mut self, direction: usize) {
         match direction {
             NORTH | SOUTH => {
                 self.window_tree.find_active_window_tree().unwrap().split_vertically();
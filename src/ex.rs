@@ -0,0 +1,579 @@
+use std;
+use std::cmp::{min, max};
+use std::fs;
+use std::path::PathBuf;
+use ncurses::*;
+use regex::Regex;
+use toml;
+
+use config;
+use drawer::Drawer;
+use editor::{Editor, SOUTH, EAST};
+use substitute;
+use util;
+
+// Ex commands by full name, with the shortest abbreviation of each.
+static EX_COMMANDS: &'static [(&'static str, &'static str)] = &[
+    ("bnext", "bn"),
+    ("bprevious", "bp"),
+    ("display", "di"),
+    ("edit", "e"),
+    ("qall", "qa"),
+    ("quit", "q"),
+    ("registers", "reg"),
+    ("set", "se"),
+    ("split", "sp"),
+    ("substitute", "s"),
+    ("vsplit", "vs"),
+    ("wq", "wq"),
+    ("write", "w"),
+    ("xit", "x"),
+];
+
+// Commands whose argument is a file name.
+static FILE_COMMANDS: &'static [&'static str] = &["edit", "split", "vsplit", "write", "wq", "xit"];
+
+// The lines an ex command acts on, counted from 0, both ends included.
+#[derive(Clone, Copy)]
+pub struct Range {
+    pub start: i32,
+    pub end: i32,
+}
+
+// Where addresses are resolved: the cursor's line, the last line, and the
+// lines of the last visual selection.
+pub struct Lines {
+    pub current: i32,
+    pub last: i32,
+    pub visual: Option<(i32, i32)>,
+}
+
+// A line number, `.`, `$`, `'<` or `'>`, each optionally followed by +N or
+// -N, at the start of `text`. Returns the line and what follows it.
+fn parse_address<'a>(text: &'a str, lines: &Lines) -> Result<Option<(i32, &'a str)>, String> {
+    let digits = |s: &str| s.chars().take_while(|c| c.is_digit(10)).count();
+    let (mut line, mut rest) = match text.chars().next() {
+        Some('.') => (lines.current, &text[1..]),
+        Some('$') => (lines.last, &text[1..]),
+        Some('\'') => {
+            let (start, end) = match lines.visual {
+                Some(visual) => visual,
+                None => { return Err("Mark not set".to_string()); }
+            };
+            match text[1..].chars().next() {
+                Some('<') => (start, &text[2..]),
+                Some('>') => (end, &text[2..]),
+                _ => { return Err("Invalid address".to_string()); }
+            }
+        },
+        Some(c) if c.is_digit(10) => {
+            let n = digits(text);
+            // line 0 is treated as the first line
+            (max(0, text[..n].parse::<i32>().unwrap_or(0) - 1), &text[n..])
+        },
+        Some('+') | Some('-') => (lines.current, text),
+        _ => { return Ok(None); }
+    };
+    while rest.starts_with("+") || rest.starts_with("-") {
+        let sign = if rest.starts_with("+") { 1 } else { -1 };
+        let n = digits(&rest[1..]);
+        let offset = if n == 0 { 1 } else { rest[1..(n + 1)].parse::<i32>().unwrap_or(0) };
+        line += sign * offset;
+        rest = &rest[(n + 1)..];
+    }
+    Ok(Some((line, rest)))
+}
+
+// Splits the range off the front of a command line: `%`, one address, or
+// two separated by a comma. None if there isn't one.
+pub fn parse_range<'a>(text: &'a str, lines: &Lines) -> Result<(Option<Range>, &'a str), String> {
+    let text = text.trim_start();
+    if text.starts_with("%") {
+        return Ok((Some(Range { start: 0, end: lines.last }), &text[1..]));
+    }
+    let (start, rest) = match parse_address(text, lines)? {
+        Some(address) => address,
+        None => { return Ok((None, text)); }
+    };
+    let (end, rest) = if rest.starts_with(",") {
+        match parse_address(&rest[1..], lines)? {
+            Some(address) => address,
+            None => (lines.current, &rest[1..]),
+        }
+    } else {
+        (start, rest)
+    };
+    if min(start, end) < 0 || max(start, end) > lines.last {
+        return Err("Invalid range".to_string());
+    }
+    Ok((Some(Range { start: min(start, end), end: max(start, end) }), rest))
+}
+
+// The command's name, whether a `!` follows it, as in :q!, and the
+// arguments after that.
+fn split_command(text: &str) -> (&str, bool, &str) {
+    let text = text.trim_start();
+    let n = text.find(|c: char| !c.is_alphabetic()).unwrap_or(text.len());
+    let args = &text[n..];
+    let bang = args.starts_with("!");
+    (&text[..n], bang, if bang { &args[1..] } else { args })
+}
+
+// The command `name` abbreviates, if any.
+fn full_name(name: &str) -> Option<&'static str> {
+    EX_COMMANDS.iter()
+        .find(|&&(full, short)| full.starts_with(name) && name.len() >= short.len())
+        .map(|&(full, _)| full)
+}
+
+fn is_substitute(name: &str) -> bool {
+    name.len() > 0 && full_name(name) == Some("substitute")
+}
+
+// :set values are numbers, true or false, or else strings.
+fn setting_value(text: &str) -> toml::Value {
+    match text {
+        "true" => toml::Value::Boolean(true),
+        "false" => toml::Value::Boolean(false),
+        _ => match text.parse::<i64>() {
+            Ok(n) => toml::Value::Integer(n),
+            Err(_) => toml::Value::String(text.to_string()),
+        }
+    }
+}
+
+// Files and directories starting with `prefix`, directories ending in `/`.
+fn complete_path(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..(i + 1)], &prefix[(i + 1)..]),
+        None => ("", prefix),
+    };
+    let entries = match fs::read_dir(if dir.len() == 0 { "." } else { dir }) {
+        Ok(entries) => entries,
+        Err(_) => { return vec![]; }
+    };
+    let mut paths: Vec<String> = entries.filter_map(|entry| entry.ok()).filter_map(|entry| {
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        // hidden files only when asked for
+        if !file_name.starts_with(name) || (file_name.starts_with(".") && !name.starts_with(".")) {
+            return None;
+        }
+        let is_dir = entry.path().is_dir();
+        Some(format!("{}{}{}", dir, file_name, if is_dir { "/" } else { "" }))
+    }).collect();
+    paths.sort();
+    paths
+}
+
+fn common_prefix(words: &[String]) -> String {
+    let mut prefix = words[0].clone();
+    for word in &words[1..] {
+        while !word.starts_with(&prefix) {
+            prefix.pop();
+        }
+    }
+    prefix
+}
+
+impl Editor {
+    // Opens the `:` prompt. From visual mode it starts with the selected
+    // lines, and a count N starts it with the next N lines.
+    pub fn start_command_line(&mut self) {
+        let window = self.window_tree.find_active_window().unwrap();
+        let mut drawer = Drawer::new_prompt("command", ":");
+        if window.in_visual_mode() {
+            // run_command sets '< and '> as visual mode ends
+            if window.mark.take().is_some() {
+                drawer.value = "'<,'>".to_string();
+            }
+        } else if let Some(count) = window.count.take() {
+            drawer.value = if count > 1 { format!(".,.+{}", count - 1) } else { ".".to_string() };
+        }
+        window.count = None;
+        window.mode = "command".to_string();
+        self.drawer = Some(drawer);
+        self.history_position = None;
+    }
+
+    pub fn handle_command_line(&mut self, key: &str) {
+        match key {
+            "<Escape>" | "<C-g>" => { self.end_command_line(); },
+            "<Enter>" => {
+                let line = self.drawer.as_ref().unwrap().value.clone();
+                self.end_command_line();
+                self.execute_command_line(&line);
+            },
+            "<Backspace>" | "<DEL>" if self.drawer.as_ref().unwrap().value.len() == 0 => { self.end_command_line(); },
+            "<Up>" | "<C-p>" => { self.recall_history(true); },
+            "<Down>" | "<C-n>" => { self.recall_history(false); },
+            "<Tab>" => { self.complete_command_line(); },
+            _ => {
+                self.history_position = None;
+                {
+                    let drawer = self.drawer.as_mut().unwrap();
+                    drawer.lines = vec![];
+                    drawer.handle_key(key);
+                }
+                self.preview_command_line();
+            }
+        }
+    }
+
+    // Steps back or forward through earlier command lines that start with
+    // what had been typed, and back to what had been typed past the newest.
+    fn recall_history(&mut self, back: bool) {
+        let value = self.drawer.as_ref().unwrap().value.clone();
+        let (index, prefix) = self.history_position.take().unwrap_or((self.command_history.len(), value));
+        let found = if back {
+            self.command_history[..index].iter().rposition(|line| line.starts_with(&prefix))
+        } else {
+            let from = min(index + 1, self.command_history.len());
+            self.command_history[from..].iter().position(|line| line.starts_with(&prefix)).map(|i| from + i)
+        };
+        {
+            let drawer = self.drawer.as_mut().unwrap();
+            drawer.lines = vec![];
+            match found {
+                Some(i) => { drawer.value = self.command_history[i].clone(); },
+                None if !back => { drawer.value = prefix.clone(); },
+                None => ()
+            }
+        }
+        self.history_position = match found {
+            Some(i) => Some((i, prefix)),
+            None if back => Some((index, prefix)),
+            None => None,
+        };
+        self.preview_command_line();
+    }
+
+    // Completes the word before the cursor: a command name, a file name or
+    // a setting. With several candidates it lists them and completes what
+    // they have in common; pressing <Tab> again goes through the list.
+    fn complete_command_line(&mut self) {
+        let mut drawer = self.drawer.take().unwrap();
+        let value = drawer.value.clone();
+        // skip the range without resolving it
+        let rest = value.trim_start_matches(|c: char| !c.is_alphabetic());
+        let (start, candidates) = match rest.rfind(' ') {
+            Some(i) => {
+                let start = value.len() - rest.len() + i + 1;
+                let word = &value[start..];
+                let (name, _, _) = split_command(rest);
+                let candidates = match full_name(name) {
+                    Some(name) if FILE_COMMANDS.contains(&name) => complete_path(word),
+                    Some("set") => {
+                        // `no` turns a setting off
+                        let (no, word) = if word.starts_with("no") { ("no", &word[2..]) } else { ("", word) };
+                        config::SETTINGS.iter()
+                            .filter(|setting| setting.starts_with(word))
+                            .map(|setting| format!("{}{}", no, setting))
+                            .collect()
+                    },
+                    _ => vec![],
+                };
+                (start, candidates)
+            },
+            None => {
+                let start = value.len() - rest.len();
+                let candidates = EX_COMMANDS.iter()
+                    .filter(|&&(full, _)| full.starts_with(rest))
+                    .map(|&(full, _)| full.to_string())
+                    .collect();
+                (start, candidates)
+            }
+        };
+
+        if drawer.lines.len() > 0 {
+            drawer.next_item();
+            let index = drawer.active_line_index as usize;
+            drawer.value = format!("{}{}", &value[..start], drawer.lines[index]);
+        } else if candidates.len() == 1 {
+            drawer.value = format!("{}{}", &value[..start], candidates[0]);
+        } else if candidates.len() > 1 {
+            drawer.value = format!("{}{}", &value[..start], common_prefix(&candidates));
+            drawer.lines = candidates;
+            // nothing picked until the next <Tab>
+            drawer.active_line_index = -1;
+            drawer.scroll_index = 0;
+        }
+        self.drawer = Some(drawer);
+    }
+
+    fn end_command_line(&mut self) {
+        self.clear_preview();
+        self.drawer = None;
+        let window = self.window_tree.find_active_window().unwrap();
+        window.mode = "normal".to_string();
+    }
+
+    fn command_lines(&mut self) -> Lines {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        Lines {
+            current: window.cursor_y,
+            last: buffer.eof() - 1,
+            visual: match (buffer.marks.get(&'<'), buffer.marks.get(&'>')) {
+                (Some(&(start, _)), Some(&(end, _))) => Some((start, end)),
+                _ => None,
+            },
+        }
+    }
+
+    pub fn execute_command_line(&mut self, line: &str) {
+        if line.trim().len() > 0 {
+            self.command_history.retain(|l| l != line);
+            self.command_history.push(line.to_string());
+        }
+        let lines = self.command_lines();
+        let (range, rest) = match parse_range(line, &lines) {
+            Ok(parsed) => parsed,
+            Err(error) => { self.message = Some(error); return; }
+        };
+        let (name, force, args) = split_command(rest);
+        let command = match full_name(name) {
+            Some(command) if name.len() > 0 => command,
+            // just a range goes to its last line
+            _ if rest.trim().len() == 0 => {
+                if let Some(range) = range {
+                    self.goto_line(range.end);
+                }
+                return;
+            },
+            _ => {
+                self.message = Some(format!("Not an editor command: {}", rest.trim()));
+                return;
+            }
+        };
+        let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        let arg = args.trim();
+        match command {
+            "substitute" => {
+                let range = range.unwrap_or(Range { start: lines.current, end: lines.current });
+                self.substitute(range, args);
+            },
+            "write" => {
+                let path = if arg.len() > 0 { Some(PathBuf::from(arg)) } else { None };
+                self.write_buffer(index, path);
+            },
+            "wq" | "xit" => {
+                let path = if arg.len() > 0 { Some(PathBuf::from(arg)) } else { None };
+                if self.write_buffer(index, path) {
+                    self.quit_window(force);
+                }
+            },
+            "quit" => { self.quit_window(force); },
+            "qall" => {
+                if force || self.refuse_unsaved((0..self.buffers.len()).collect()) {
+                    endwin();
+                    std::process::exit(0);
+                }
+            },
+            "edit" => { self.edit(arg, force); },
+            "split" | "vsplit" => {
+                self.split_towards(if command == "split" { SOUTH } else { EAST });
+                if arg.len() > 0 {
+                    // the buffer is still shown in the other window
+                    self.edit(arg, true);
+                }
+            },
+            "bnext" => { self.cycle_buffer(1, force); },
+            "bprevious" => { self.cycle_buffer(-1, force); },
+            "set" => { self.set_options(arg); },
+            "registers" | "display" => { self.list_registers(); },
+            _ => ()
+        }
+    }
+
+    // Writes the buffer to its file, or to `path`, saying how it went on
+    // the message line. Returns whether it worked.
+    pub fn write_buffer(&mut self, index: usize, path: Option<PathBuf>) -> bool {
+        let path = path.unwrap_or(self.buffers[index].path.clone());
+        match self.buffers[index].write_to(&path) {
+            Ok(bytes) => {
+                if path == self.buffers[index].path {
+                    self.buffers[index].mark_saved();
+                }
+                self.message = Some(format!("\"{}\" {}L, {}B written", path.display(), self.buffers[index].eof(), bytes));
+                self.update_file_marks(index);
+                true
+            },
+            Err(error) => {
+                self.message = Some(format!("Can't write {}: {}", path.display(), error));
+                false
+            }
+        }
+    }
+
+    // Whether none of the buffers at `indices` has changes that haven't
+    // been written. Says which one has, if one does.
+    fn refuse_unsaved(&mut self, indices: Vec<usize>) -> bool {
+        match indices.into_iter().find(|&i| self.buffers[i].is_modified()) {
+            Some(i) => {
+                self.message = Some(format!("No write since last change for \"{}\" (add ! to override)", self.buffers[i].path.display()));
+                false
+            },
+            None => true,
+        }
+    }
+
+    // Closes the active window, or the editor if it's the last one. Unless
+    // forced, the window's buffer, or on quitting any buffer, mustn't have
+    // unsaved changes.
+    fn quit_window(&mut self, force: bool) {
+        let last = self.window_tree.branches.len() == 0;
+        let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        let indices = if last { (0..self.buffers.len()).collect() } else { vec![index] };
+        if !force && !self.refuse_unsaved(indices) {
+            return;
+        }
+        if last {
+            endwin();
+            std::process::exit(0);
+        }
+        self.window_tree.find_active_window_tree().unwrap().destroy();
+    }
+
+    // Shows the file in the active window, opening it unless it's open.
+    // Unless forced, the buffer it replaces mustn't have unsaved changes.
+    fn edit(&mut self, path: &str, force: bool) {
+        if path.len() == 0 {
+            self.message = Some("No file name".to_string());
+            return;
+        }
+        let path = PathBuf::from(path);
+        let current = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        if !force && self.buffers[current].path != path && !self.refuse_unsaved(vec![current]) {
+            return;
+        }
+        let index = match self.buffers.iter().position(|b| b.path == path) {
+            Some(index) => index,
+            None if path.is_file() => {
+                self.open(path);
+                self.buffers.len() - 1
+            },
+            None => {
+                self.message = Some(format!("Can't open {}", path.display()));
+                return;
+            }
+        };
+        self.show_buffer(index);
+    }
+
+    fn cycle_buffer(&mut self, step: i32, force: bool) {
+        let n = self.buffers.len() as i32;
+        let index = self.window_tree.find_active_window().unwrap().buffer_index;
+        if !force && n > 1 && !self.refuse_unsaved(vec![index as usize]) {
+            return;
+        }
+        self.show_buffer(((index + step) % n + n) as usize % n as usize);
+    }
+
+    fn show_buffer(&mut self, index: usize) {
+        self.push_jump();
+        self.window_tree.find_active_window().unwrap().show_buffer(index as i32);
+    }
+
+    fn goto_line(&mut self, y: i32) {
+        self.push_jump();
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        window.cursor_y = y;
+        window.cursor_x = buffer.first_non_blank(y);
+        window.row = window.cursor_y;
+        window.col = window.cursor_x;
+        window.scroll_to_cursor(window_height);
+    }
+
+    // :set name=value, name or noname for true or false, and name? to
+    // show a value. Applies to the active buffer.
+    fn set_options(&mut self, args: &str) {
+        let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        let ref mut settings = self.buffers[index].settings;
+        let mut errors = vec![];
+        let mut shown = vec![];
+        let names: Vec<&str> = if args.len() == 0 {
+            config::SETTINGS.iter().map(|name| *name).collect()
+        } else {
+            args.split_whitespace().collect()
+        };
+        for arg in names {
+            let (name, value) = match arg.find('=') {
+                Some(i) => (&arg[..i], Some(setting_value(&arg[(i + 1)..]))),
+                None if arg.ends_with("?") => (&arg[..(arg.len() - 1)], None),
+                None if arg.starts_with("no") && settings.get(&arg[2..]).is_some() => {
+                    (&arg[2..], Some(toml::Value::Boolean(false)))
+                },
+                None => match settings.get(arg) {
+                    Some(ref v) if args.len() > 0 && (v == "true" || v == "false") => {
+                        (arg, Some(toml::Value::Boolean(true)))
+                    },
+                    _ => (arg, None),
+                },
+            };
+            match value {
+                Some(value) => {
+                    if !settings.set(name, &value, &mut errors) {
+                        errors.push(format!("unknown setting `{}`", name));
+                    }
+                },
+                None => match settings.get(name) {
+                    Some(v) => { shown.push(format!("{}={}", name, v)); },
+                    None => { errors.push(format!("unknown setting `{}`", name)); }
+                }
+            }
+        }
+        self.message = if errors.len() > 0 {
+            Some(errors.join(", "))
+        } else if shown.len() > 0 {
+            Some(shown.join("  "))
+        } else {
+            None
+        };
+    }
+
+    // Shows what a substitution being typed would do, once its replacement
+    // has been started.
+    fn preview_command_line(&mut self) {
+        self.clear_preview();
+        let line = self.drawer.as_ref().unwrap().value.clone();
+        let lines = self.command_lines();
+        if let Ok((range, rest)) = parse_range(&line, &lines) {
+            let (name, _, args) = split_command(rest);
+            if is_substitute(name) {
+                let range = range.unwrap_or(Range { start: lines.current, end: lines.current });
+                self.command_preview = self.preview_substitute(range, args);
+            }
+        }
+    }
+
+    // Takes back the preview, if there is one.
+    fn clear_preview(&mut self) {
+        if self.command_preview {
+            let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+            self.buffers[index].undo();
+            self.command_preview = false;
+        }
+    }
+
+    // The pattern of a substitution whose replacement hasn't been started,
+    // to highlight while it's typed.
+    pub fn command_line_pattern(&self) -> Option<Regex> {
+        let drawer = match self.drawer {
+            Some(ref drawer) if drawer.kind == "command" => drawer,
+            _ => { return None; }
+        };
+        // skip the range without resolving it
+        let rest = drawer.value.trim_start_matches(|c: char| !c.is_alphabetic());
+        let (name, _, args) = split_command(rest);
+        if !is_substitute(name) {
+            return None;
+        }
+        match substitute::parse(args) {
+            Some(ref parsed) if parsed.replacement.is_none() => parsed.regex(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::cmp::min;
+
+use buffer::Buffer;
+use motion;
+
+// Characters after which languages that don't use the default `{([`
+// indent the next line, keyed by syntect syntax name.
+pub static LANGUAGE_OPENERS: &'static [(&'static str, &'static str)] = &[
+    ("Makefile", ":"),
+    ("Plain Text", ""),
+    ("Python", "{([:"),
+    ("YAML", "{[:"),
+];
+
+pub fn openers_for(syntax: &str) -> Option<&'static str> {
+    LANGUAGE_OPENERS.iter().find(|&&(name, _)| name == syntax).map(|&(_, openers)| openers)
+}
+
+// The whitespace at the start of line y.
+pub fn indentation(buffer: &Buffer, y: i32) -> String {
+    buffer.lines[y as usize].iter().map(|c| c.ch).take_while(|&ch| ch == ' ' || ch == '\t').collect()
+}
+
+// The indentation for a line broken off line y at x: the same as line y,
+// and a level deeper after one of the buffer's openers.
+pub fn new_line(buffer: &Buffer, y: i32, x: i32) -> String {
+    let mut indent = indentation(buffer, y);
+    indent.truncate(x as usize);
+    let before: String = buffer.lines[y as usize].iter().take(x as usize).map(|c| c.ch).collect();
+    match before.trim_end().chars().last() {
+        Some(ch) if buffer.settings.indent_after.contains(ch) => { indent + &buffer.indent_unit() },
+        _ => indent,
+    }
+}
+
+// Works out the indentation of lines first to last from the lines above
+// them, the way auto-indent would have as they were typed: a level deeper
+// after an opener, and closing brackets lined up with their openers.
+pub fn reindent(buffer: &mut Buffer, first: i32, last: i32) {
+    let shift = buffer.settings.shift_width();
+    // where blocks open with `:` nothing closes them, so only the author
+    // knows where they end; lines are never indented further than they were
+    let keep_dedents = buffer.settings.indent_after.contains(':');
+    for y in first..(last + 1) {
+        let text = buffer.line_text(y);
+        let x = text.chars().take_while(|c| c.is_whitespace()).count() as i32;
+        let aligned = match text.trim_start().chars().next() {
+            None => {
+                buffer.set_indent_width(y, 0);
+                continue;
+            },
+            Some(close) if close == ')' || close == ']' || close == '}' => {
+                let open = match close { ')' => '(', ']' => '[', _ => '{' };
+                motion::find_bracket(buffer, (x, y), open, close, false, &None, false)
+                    .map(|(_, open_y)| buffer.indent_width(open_y))
+            },
+            Some(_) => None,
+        };
+        let width = match aligned {
+            Some(width) => width,
+            None => {
+                match (0..y).rev().find(|&p| buffer.line_text(p).trim().len() > 0) {
+                    Some(above) => {
+                        let base = buffer.indent_width(above);
+                        let opens = buffer.line_text(above).trim_end().chars().last()
+                            .map(|c| buffer.settings.indent_after.contains(c))
+                            .unwrap_or(false);
+                        if opens {
+                            base + shift
+                        } else if keep_dedents {
+                            min(base, buffer.indent_width(y))
+                        } else {
+                            base
+                        }
+                    },
+                    None => 0,
+                }
+            }
+        };
+        buffer.set_indent_width(y, width);
+    }
+}
+
+// The closer of `open`, if it's a bracket.
+pub fn closer(open: char) -> Option<char> {
+    match open {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        _ => None,
+    }
+}
+
+// Lines up a closing bracket just typed at x as the first thing on its
+// line with the line that opened it. Returns where the bracket ends up.
+pub fn align_closer(buffer: &mut Buffer, x: i32, y: i32) -> i32 {
+    let close = buffer.lines[y as usize][x as usize].ch;
+    let open = match close {
+        ')' => '(',
+        ']' => '[',
+        _ => '{',
+    };
+    let current = indentation(buffer, y);
+    if current.chars().count() as i32 != x {
+        return x;
+    }
+    let indent = match motion::find_bracket(buffer, (x, y), open, close, false, &None, false) {
+        Some((_, open_y)) => indentation(buffer, open_y),
+        None => { return x; }
+    };
+    if indent != current {
+        buffer.remove_text(0, y, x as usize, true);
+        if indent.len() > 0 {
+            buffer.insert(&indent, 0, y, true);
+        }
+    }
+    indent.chars().count() as i32
+}
@@ -0,0 +1,335 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufRead;
+use std::path::Path;
+
+use command;
+
+#[derive(Clone)]
+pub struct Binding {
+    pub mode: String,
+    pub keys: Vec<String>,
+    pub command: String,
+}
+
+pub struct Keymap {
+    pub bindings: Vec<Binding>,
+}
+
+// (mode, keys, command). "global" bindings apply in every mode.
+static DEFAULT_BINDINGS: &'static [(&'static str, &'static str, &'static str)] = &[
+    ("global", "<M-h>", "focus-west"),
+    ("global", "<M-j>", "focus-south"),
+    ("global", "<M-k>", "focus-north"),
+    ("global", "<M-l>", "focus-east"),
+    ("global", "<M-H>", "split-west"),
+    ("global", "<M-J>", "split-south"),
+    ("global", "<M-K>", "split-north"),
+    ("global", "<M-L>", "split-east"),
+    ("global", "<C-q>", "close-window"),
+    ("global", "<F1>", "describe-bindings"),
+    ("global", "<F5>", "reload-config"),
+    ("global", "<M-x>", "execute-command"),
+    ("normal", "$", "move-eol"),
+    ("normal", "0", "move-bol"),
+    ("normal", "a", "append"),
+    ("normal", "A", "append-eol"),
+    ("normal", "b", "move-word-backward"),
+    ("normal", "B", "move-big-word-backward"),
+    ("normal", "c", "operator-change"),
+    ("normal", "C", "change-to-eol"),
+    ("normal", "d", "operator-delete"),
+    ("normal", "D", "delete-to-eol"),
+    ("normal", "e", "move-word-end"),
+    ("normal", "E", "move-big-word-end"),
+    ("normal", "f", "find-char"),
+    ("normal", "F", "find-char-backwards"),
+    ("normal", "gg", "goto-first-line"),
+    ("normal", "gu", "operator-lowercase"),
+    ("normal", "gU", "operator-uppercase"),
+    ("normal", "g~", "operator-switch-case"),
+    ("normal", "gJ", "join-lines-without-spaces"),
+    ("normal", "gc", "operator-comment"),
+    ("normal", "gcc", "comment-line"),
+    ("normal", "gq", "operator-format"),
+    ("normal", "gqq", "format-line"),
+    ("normal", "G", "goto-last-line"),
+    ("normal", "h", "move-left"),
+    ("normal", "H", "move-window-top"),
+    ("normal", "i", "insert-mode"),
+    ("normal", "I", "insert-bol"),
+    ("normal", "J", "join-lines"),
+    ("normal", "j", "move-down"),
+    ("normal", "k", "move-up"),
+    ("normal", "l", "move-right"),
+    ("normal", "L", "move-window-bottom"),
+    ("normal", "M", "move-window-middle"),
+    ("normal", "n", "search-next"),
+    ("normal", "N", "search-previous"),
+    ("normal", "O", "open-line-above"),
+    ("normal", "o", "open-line-below"),
+    ("normal", "p", "paste"),
+    ("normal", "P", "paste-before"),
+    ("normal", "q", "record-macro"),
+    ("normal", "@", "play-macro"),
+    ("normal", "r", "replace-char"),
+    ("normal", "R", "replace-mode"),
+    ("normal", "s", "substitute-char"),
+    ("normal", "S", "substitute-line"),
+    ("normal", ".", "repeat-change"),
+    ("normal", "m", "set-mark"),
+    ("normal", "'", "goto-mark-line"),
+    ("normal", "`", "goto-mark"),
+    ("normal", "<C-o>", "jump-back"),
+    ("normal", "<C-i>", "jump-forward"),
+    ("normal", "<Tab>", "jump-forward"),
+    ("normal", "t", "till-char"),
+    ("normal", "T", "till-char-backwards"),
+    ("normal", "u", "undo"),
+    ("normal", "v", "visual-mode"),
+    ("normal", "V", "visual-line-mode"),
+    ("normal", "<C-v>", "visual-block-mode"),
+    ("normal", "w", "move-word-forward"),
+    ("normal", "W", "move-big-word-forward"),
+    ("normal", "x", "delete-char"),
+    ("normal", "y", "operator-yank"),
+    ("normal", "Y", "yank-line"),
+    ("normal", "~", "switch-case"),
+    ("normal", "<C-a>", "increment-number"),
+    ("normal", "<C-x>", "decrement-number"),
+    ("normal", ">", "operator-indent"),
+    ("normal", "<lt>", "operator-dedent"),
+    ("normal", "=", "operator-reindent"),
+    ("insert", "<C-t>", "indent-line"),
+    ("insert", "<C-d>", "dedent-line"),
+    ("normal", "{", "move-paragraph-backward"),
+    ("normal", "}", "move-paragraph-forward"),
+    ("normal", "(", "move-sentence-backward"),
+    ("normal", ")", "move-sentence-forward"),
+    ("normal", "%", "match-bracket"),
+    ("normal", "/", "search-forward"),
+    ("normal", "?", "search-backward"),
+    ("normal", "*", "search-word-forward"),
+    ("normal", "#", "search-word-backward"),
+    ("normal", ":", "command-line"),
+    ("normal", "<C-l>", "clear-search-highlight"),
+    ("normal", "\"", "select-register"),
+    ("normal", "<M-y>", "yank-pop"),
+    ("normal", "<C-n>", "add-cursor-next-match"),
+    ("normal", "<C-Up>", "add-cursor-above"),
+    ("normal", "<C-Down>", "add-cursor-below"),
+    ("normal", "<C-b>", "page-up"),
+    ("normal", "<C-c>", "quit"),
+    ("normal", "<C-f>", "page-down"),
+    ("normal", "<C-s>", "save"),
+    ("normal", "<C-x><C-f>", "find-files"),
+    ("normal", "<C-x><C-k>e", "edit-macro"),
+    ("operator", "<Escape>", "normal-mode"),
+    ("operator", "iw", "inner-word"),
+    ("operator", "aw", "around-word"),
+    ("operator", "i\"", "inner-double-quote"),
+    ("operator", "a\"", "around-double-quote"),
+    ("operator", "i'", "inner-single-quote"),
+    ("operator", "a'", "around-single-quote"),
+    ("operator", "i(", "inner-paren"),
+    ("operator", "a(", "around-paren"),
+    ("operator", "i)", "inner-paren"),
+    ("operator", "a)", "around-paren"),
+    ("operator", "ib", "inner-paren"),
+    ("operator", "ab", "around-paren"),
+    ("operator", "i{", "inner-brace"),
+    ("operator", "a{", "around-brace"),
+    ("operator", "i}", "inner-brace"),
+    ("operator", "a}", "around-brace"),
+    ("operator", "iB", "inner-brace"),
+    ("operator", "aB", "around-brace"),
+    ("operator", "i[", "inner-bracket"),
+    ("operator", "a[", "around-bracket"),
+    ("operator", "i]", "inner-bracket"),
+    ("operator", "a]", "around-bracket"),
+    ("operator", "i<lt>", "inner-angle"),
+    ("operator", "a<lt>", "around-angle"),
+    ("operator", "i>", "inner-angle"),
+    ("operator", "a>", "around-angle"),
+    ("operator", "it", "inner-tag"),
+    ("operator", "at", "around-tag"),
+    ("operator", "ip", "inner-paragraph"),
+    ("operator", "ap", "around-paragraph"),
+    ("operator", "if", "inner-function"),
+    ("operator", "af", "around-function"),
+    ("visual", "<Escape>", "normal-mode"),
+    ("visual", "d", "delete-region"),
+    ("visual", "x", "delete-region"),
+    ("visual", "y", "yank-region"),
+    ("visual", ":", "command-line"),
+    ("visual", "<M-c>", "add-cursors-to-lines"),
+    ("visual", "S", "wrap-selection"),
+    ("visual", "u", "operator-lowercase"),
+    ("visual", "U", "operator-uppercase"),
+    ("visual", "~", "operator-switch-case"),
+    ("visual", "s", "operator-change"),
+    ("visual_block", "I", "block-insert"),
+    ("visual_block", "A", "block-append"),
+    ("visual_block", "<C-x>ro", "open-rectangle"),
+    ("visual_block", "<C-x>rt", "string-rectangle"),
+    ("visual", "iw", "inner-word"),
+    ("visual", "aw", "around-word"),
+    ("visual", "i\"", "inner-double-quote"),
+    ("visual", "a\"", "around-double-quote"),
+    ("visual", "i'", "inner-single-quote"),
+    ("visual", "a'", "around-single-quote"),
+    ("visual", "i(", "inner-paren"),
+    ("visual", "a(", "around-paren"),
+    ("visual", "i)", "inner-paren"),
+    ("visual", "a)", "around-paren"),
+    ("visual", "ib", "inner-paren"),
+    ("visual", "ab", "around-paren"),
+    ("visual", "i{", "inner-brace"),
+    ("visual", "a{", "around-brace"),
+    ("visual", "i}", "inner-brace"),
+    ("visual", "a}", "around-brace"),
+    ("visual", "iB", "inner-brace"),
+    ("visual", "aB", "around-brace"),
+    ("visual", "i[", "inner-bracket"),
+    ("visual", "a[", "around-bracket"),
+    ("visual", "i]", "inner-bracket"),
+    ("visual", "a]", "around-bracket"),
+    ("visual", "i<lt>", "inner-angle"),
+    ("visual", "a<lt>", "around-angle"),
+    ("visual", "i>", "inner-angle"),
+    ("visual", "a>", "around-angle"),
+    ("visual", "it", "inner-tag"),
+    ("visual", "at", "around-tag"),
+    ("visual", "ip", "inner-paragraph"),
+    ("visual", "ap", "around-paragraph"),
+    ("visual", "if", "inner-function"),
+    ("visual", "af", "around-function"),
+];
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        let mut keymap = Keymap {
+            bindings: vec![],
+        };
+        for &(mode, keys, command) in DEFAULT_BINDINGS {
+            keymap.bind(mode, keys, command);
+        }
+        keymap
+    }
+
+    pub fn bind(&mut self, mode: &str, keys: &str, command: &str) {
+        let keys = parse_keys(keys);
+        self.bindings.retain(|b| !(b.mode == mode && b.keys == keys));
+        if command != "unbound" {
+            self.bindings.push(Binding {
+                mode: mode.to_string(),
+                keys: keys,
+                command: command.to_string(),
+            });
+        }
+    }
+
+    // Each non-empty line is `mode keys command`, e.g. `normal <C-n> move-down`.
+    // Binding a sequence to `unbound` removes it.
+    pub fn load(&mut self, path: &Path) -> Vec<String> {
+        let mut errors = vec![];
+        if let Ok(f) = File::open(path) {
+            for (index, line) in BufReader::new(f).lines().enumerate() {
+                let line = line.unwrap_or(String::new());
+                let line = line.trim();
+                if line.len() == 0 || line.starts_with("#") { continue; }
+                let words: Vec<&str> = line.split_whitespace().collect();
+                if words.len() != 3 {
+                    errors.push(format!("{}:{}: expected `mode keys command`", path.display(), index + 1));
+                    continue;
+                }
+                if words[2] != "unbound" && !command::is_command(words[2]) {
+                    errors.push(format!("{}:{}: unknown command `{}`", path.display(), index + 1, words[2]));
+                    continue;
+                }
+                self.bind(words[0], words[1], words[2]);
+            }
+        }
+        errors
+    }
+
+    // Returns the command bound to exactly `keys`, and whether `keys` is
+    // also the beginning of a longer binding.
+    pub fn lookup(&self, mode: &str, keys: &[String]) -> (Option<String>, bool) {
+        let mut command = None;
+        let mut is_prefix = false;
+        for m in mode_chain(mode) {
+            for binding in self.bindings.iter().filter(|b| b.mode == m) {
+                if binding.keys.as_slice() == keys {
+                    if command.is_none() {
+                        command = Some(binding.command.clone());
+                    }
+                } else if binding.keys.len() > keys.len() && binding.keys.starts_with(keys) {
+                    is_prefix = true;
+                }
+            }
+        }
+        (command, is_prefix)
+    }
+
+    // The key sequences bound to `command` in any mode.
+    pub fn bindings_for(&self, command: &str) -> Vec<String> {
+        let mut keys: Vec<String> = vec![];
+        for binding in self.bindings.iter().filter(|b| b.command == command) {
+            let k = format_keys(&binding.keys);
+            if !keys.contains(&k) {
+                keys.push(k);
+            }
+        }
+        keys
+    }
+}
+
+// Modes fall back on other modes' bindings, most specific first.
+fn mode_chain(mode: &str) -> Vec<&str> {
+    match mode {
+        "visual" | "operator" => vec![mode, "normal", "global"],
+        "visual_line" | "visual_block" => vec![mode, "visual", "normal", "global"],
+        // R and :s///c keep an undo group open until they end, so nothing
+        // else may take the window away meanwhile
+        "global" | "overtype" | "substitute_confirm" => vec![mode],
+        _ => vec![mode, "global"],
+    }
+}
+
+// Splits "<C-x><C-f>" into ["<C-x>", "<C-f>"] and "gg" into ["g", "g"].
+// "<lt>" and "<Space>" stand for the literal keys.
+pub fn parse_keys(keys: &str) -> Vec<String> {
+    let mut result = vec![];
+    let chars: Vec<char> = keys.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] == '<' {
+            if let Some(end) = chars[i..].iter().position(|&c| c == '>') {
+                if end > 1 {
+                    let key: String = chars[i..(i + end + 1)].iter().cloned().collect();
+                    result.push(match key.as_str() {
+                        "<lt>" => "<".to_string(),
+                        "<Space>" => " ".to_string(),
+                        _ => key,
+                    });
+                    i += end + 1;
+                    continue;
+                }
+            }
+        }
+        result.push(chars[i].to_string());
+        i += 1;
+    }
+    result
+}
+
+pub fn format_keys(keys: &[String]) -> String {
+    keys.iter().map(|k| {
+        match k.as_str() {
+            "<" => "<lt>".to_string(),
+            " " => "<Space>".to_string(),
+            _ => k.clone(),
+        }
+    }).collect()
+}
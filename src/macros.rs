@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use editor::{Editor, SOUTH};
+use keymap;
+use util;
+
+// How deep macros may call other macros before playback gives up.
+const MAX_DEPTH: usize = 100;
+
+pub fn path() -> PathBuf {
+    util::config_dir().join("macros")
+}
+
+// Each line is a register followed by its keys, e.g. `a dw<Enter>`.
+pub fn load() -> HashMap<char, Vec<String>> {
+    let mut macros = HashMap::new();
+    if let Ok(f) = File::open(path()) {
+        for line in BufReader::new(f).lines() {
+            let line = line.unwrap_or(String::new());
+            let mut chars = line.chars();
+            if let Some(register) = chars.next() {
+                if is_register(register) && chars.next() == Some(' ') {
+                    macros.insert(register, keymap::parse_keys(chars.as_str()));
+                }
+            }
+        }
+    }
+    macros
+}
+
+pub fn save(macros: &HashMap<char, Vec<String>>) -> Result<(), String> {
+    let mut registers: Vec<&char> = macros.keys().collect();
+    registers.sort();
+    let mut s = String::new();
+    for register in registers {
+        s.push_str(&format!("{} {}\n", register, keymap::format_keys(&macros[register])));
+    }
+    let _ = fs::create_dir_all(util::config_dir());
+    match File::create(path()) {
+        Ok(mut f) => f.write_all(s.as_bytes()).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn is_register(ch: char) -> bool {
+    ch >= 'a' && ch <= 'z'
+}
+
+fn single_char(key: &str) -> Option<char> {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Some(ch),
+        _ => None,
+    }
+}
+
+impl Editor {
+    pub fn handle_record_macro(&mut self, key: &str) {
+        self.window_tree.find_active_window().unwrap().mode = "normal".to_string();
+        match single_char(key) {
+            // an uppercase register appends to the lowercase one
+            Some(ch) if is_register(ch.to_lowercase().next().unwrap()) => {
+                let register = ch.to_lowercase().next().unwrap();
+                let keys = if ch != register {
+                    self.macros.get(&register).cloned().unwrap_or(vec![])
+                } else {
+                    vec![]
+                };
+                self.recording = Some((register, keys));
+            },
+            _ => ()
+        }
+    }
+
+    pub fn stop_recording(&mut self) {
+        if let Some((register, mut keys)) = self.recording.take() {
+            // drop the keys that stopped the recording
+            for binding in self.keymap.bindings.iter().filter(|b| b.command == "record-macro") {
+                if keys.ends_with(&binding.keys) {
+                    let n = keys.len() - binding.keys.len();
+                    keys.truncate(n);
+                    break;
+                }
+            }
+            self.macros.insert(register, keys);
+            if let Err(e) = save(&self.macros) {
+                self.message = Some(format!("Couldn't save macros: {}", e));
+            }
+        }
+    }
+
+    pub fn handle_play_macro(&mut self, key: &str) {
+        let count = {
+            let window = self.window_tree.find_active_window().unwrap();
+            window.mode = "normal".to_string();
+            window.take_count() as usize
+        };
+        let register = match single_char(key) {
+            Some('@') => self.last_macro,
+            Some(ch) if is_register(ch) => Some(ch),
+            _ => None,
+        };
+        if let Some(register) = register {
+            self.play_macro(register, count);
+        }
+    }
+
+    pub fn play_macro(&mut self, register: char, count: usize) {
+        let keys = match self.macros.get(&register) {
+            Some(keys) => keys.clone(),
+            None => {
+                self.message = Some(format!("Register {} is empty", register));
+                return;
+            }
+        };
+        if self.macro_depth >= MAX_DEPTH {
+            self.message = Some("Macro recursion is too deep".to_string());
+            return;
+        }
+        self.last_macro = Some(register);
+        self.macro_depth += 1;
+        let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        if count > 1 {
+            self.buffers[index].begin_group();
+        }
+        for _ in 0..count {
+            for key in &keys {
+                self.process_key(key);
+            }
+        }
+        if count > 1 {
+            self.buffers[index].end_group();
+        }
+        self.macro_depth -= 1;
+    }
+
+    pub fn handle_edit_macro(&mut self, key: &str) {
+        self.window_tree.find_active_window().unwrap().mode = "normal".to_string();
+        match single_char(key) {
+            Some(register) if is_register(register) => {
+                let text = keymap::format_keys(&self.macros.get(&register).cloned().unwrap_or(vec![]));
+                self.split_towards(SOUTH);
+                self.open_scratch(&format!("*macro-{}*", register), vec![text]);
+                let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+                self.buffers[index].macro_register = Some(register);
+            },
+            _ => ()
+        }
+    }
+
+    // Saving a macro buffer stores its text back into the register.
+    pub fn store_macro(&mut self, index: usize) {
+        if let Some(register) = self.buffers[index].macro_register {
+            let text: String = self.buffers[index].lines.iter()
+                .map(|line| line.iter().map(|c| c.ch).collect::<String>())
+                .collect();
+            self.macros.insert(register, keymap::parse_keys(&text));
+            self.buffers[index].mark_saved();
+            self.message = match save(&self.macros) {
+                Ok(_) => Some(format!("Stored macro {}", register)),
+                Err(e) => Some(format!("Couldn't save macros: {}", e)),
+            };
+        }
+    }
+}
@@ -4,12 +4,10 @@ extern crate termkey;
 extern crate libc;
 extern crate regex;
 extern crate fuzzyrusty;
+extern crate toml;
 #[macro_use]
 extern crate lazy_static;
 
-static COLOR_PAIR_DEFAULT: i16 = 1;
-static COLOR_PAIR_HIGHLIGHT: i16 = 2;
-
 use std::{env};
 use ncurses::*;
 use termkey::*;
@@ -26,6 +24,29 @@ mod cell;
 mod util;
 mod drawer;
 mod transaction;
+mod keymap;
+mod command;
+mod config;
+mod macros;
+mod motion;
+mod operator;
+mod clipboard;
+mod syntax;
+mod text_object;
+mod search;
+mod ex;
+mod substitute;
+mod palette;
+mod register;
+mod block;
+mod cursors;
+mod repeat;
+mod marks;
+mod indent;
+mod pairs;
+mod comment;
+mod number;
+mod wrap;
 
 fn main() {
     initscr();
@@ -40,9 +61,6 @@ fn main() {
         init_pair(i, i, -1);
     }
 
-    init_pair(COLOR_PAIR_HIGHLIGHT, 0, 10);
-    init_pair(COLOR_PAIR_DEFAULT, 3, -1);
-
     let ed = &mut Editor::new();
 
     if let Some(filename) = env::args().nth(1) {
@@ -55,6 +73,7 @@ fn main() {
             ed.draw();
             let p = poll::poll_rd1(0, wait);
             if p == 0 {
+                ed.handle_timeout();
                 match tk.getkey_force() {
                     TermKeyResult::Key(key) => {
                         ed.handle_input(&tk.strfkey(key, c::TERMKEY_FORMAT_VIM));
@@ -0,0 +1,308 @@
+use std::cmp::{min, max};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use editor::Editor;
+use motion::Target;
+use util;
+
+// How many places each window's jump list remembers.
+const JUMP_LIST_SIZE: usize = 100;
+
+// Motions that can go far enough to be worth coming back from with <C-o>.
+pub static JUMPS: &'static [&'static str] = &[
+    "goto-first-line",
+    "goto-last-line",
+    "match-bracket",
+    "move-paragraph-backward",
+    "move-paragraph-forward",
+    "move-sentence-backward",
+    "move-sentence-forward",
+    "move-window-bottom",
+    "move-window-middle",
+    "move-window-top",
+    "search-next",
+    "search-previous",
+    "search-word-backward",
+    "search-word-forward",
+];
+
+pub fn path() -> PathBuf {
+    util::config_dir().join("marks")
+}
+
+// Each line is a mark, its line and column, and the file, e.g. `A 12 4 src/main.rs`.
+pub fn load() -> HashMap<char, (PathBuf, i32, i32)> {
+    let mut marks = HashMap::new();
+    if let Ok(f) = File::open(path()) {
+        for line in BufReader::new(f).lines() {
+            let line = line.unwrap_or(String::new());
+            let fields: Vec<&str> = line.splitn(4, ' ').collect();
+            if fields.len() < 4 {
+                continue;
+            }
+            let name = fields[0].chars().next().unwrap_or(' ');
+            if let (true, Ok(y), Ok(x)) = (is_file_mark(name), fields[1].parse(), fields[2].parse()) {
+                marks.insert(name, (PathBuf::from(fields[3]), y, x));
+            }
+        }
+    }
+    marks
+}
+
+pub fn save(marks: &HashMap<char, (PathBuf, i32, i32)>) -> Result<(), String> {
+    let mut names: Vec<&char> = marks.keys().collect();
+    names.sort();
+    let mut s = String::new();
+    for name in names {
+        let (ref path, y, x) = marks[name];
+        s.push_str(&format!("{} {} {} {}\n", name, y, x, path.display()));
+    }
+    let _ = fs::create_dir_all(util::config_dir());
+    match File::create(path()) {
+        Ok(mut f) => f.write_all(s.as_bytes()).map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// The full path of a buffer's file, so a file mark still finds it when the
+// editor is started from another directory.
+fn full_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| env::current_dir().map(|dir| dir.join(path)).unwrap_or(path.to_path_buf()))
+}
+
+// A to Z are marks across files.
+fn is_file_mark(ch: char) -> bool {
+    ch >= 'A' && ch <= 'Z'
+}
+
+fn single_char(key: &str) -> Option<char> {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Some(ch),
+        _ => None,
+    }
+}
+
+impl Editor {
+    // m{a-z} marks the cursor's place in the buffer, m{A-Z} in the file.
+    pub fn handle_set_mark(&mut self, key: &str) {
+        let window = self.window_tree.find_active_window().unwrap();
+        window.mode = "normal".to_string();
+        let ref mut buffer = self.buffers[window.buffer_index as usize];
+        let position = (window.cursor_y, window.cursor_x);
+        match single_char(key) {
+            Some(ch) if ch.is_ascii_lowercase() || ch == '\'' || ch == '`' => {
+                buffer.marks.insert(if ch == '`' { '\'' } else { ch }, position);
+            },
+            Some(ch) if is_file_mark(ch) => {
+                buffer.marks.insert(ch, position);
+                self.file_marks.insert(ch, (full_path(&buffer.path), position.0, position.1));
+                if let Err(e) = save(&self.file_marks) {
+                    self.message = Some(format!("Couldn't save marks: {}", e));
+                }
+            },
+            _ => ()
+        }
+    }
+
+    // '{mark} goes to the first non-blank of the mark's line and `{mark} to
+    // the mark itself. Either can follow an operator.
+    pub fn handle_goto_mark(&mut self, key: &str, linewise: bool) {
+        let name = match single_char(key) {
+            Some('`') => '\'',
+            Some(ch) if key != "<Escape>" => ch,
+            _ => {
+                self.window_tree.find_active_window().unwrap().cancel_operator();
+                return;
+            }
+        };
+        let jumping = {
+            let window = self.window_tree.find_active_window().unwrap();
+            window.mode = "normal".to_string();
+            window.operator.is_none()
+        };
+        // the ' mark is read before the jump moves it
+        let local = if is_file_mark(name) { None } else { Some(self.mark_position(name)) };
+        if jumping {
+            self.push_jump();
+        }
+        let position = match local {
+            Some(position) => position,
+            None if self.show_file_mark(name) => self.mark_position(name),
+            None => {
+                self.window_tree.find_active_window().unwrap().cancel_operator();
+                return;
+            }
+        };
+        let (y, x) = match position {
+            Some(position) => position,
+            None => {
+                self.message = Some(format!("Mark not set: {}", name));
+                self.window_tree.find_active_window().unwrap().cancel_operator();
+                return;
+            }
+        };
+        let target = {
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref buffer = self.buffers[window.buffer_index as usize];
+            let y = max(0, min(y, buffer.eof() - 1));
+            let x = if linewise { buffer.first_non_blank(y) } else { x };
+            Target { x: x, y: y, linewise: linewise, inclusive: false }
+        };
+        self.finish_motion(target);
+    }
+
+    fn mark_position(&mut self, name: char) -> Option<(i32, i32)> {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        match name {
+            // where the last change was
+            '.' => buffer.transactions.last().map(|t| (t.y, t.x)),
+            _ => buffer.marks.get(&name).cloned(),
+        }
+    }
+
+    // Shows the file a file mark is in, opening it if need be. The mark's
+    // place is then kept in the buffer.
+    fn show_file_mark(&mut self, name: char) -> bool {
+        let (path, y, x) = match self.file_marks.get(&name) {
+            Some(mark) => mark.clone(),
+            None => {
+                self.message = Some(format!("Mark not set: {}", name));
+                return false;
+            }
+        };
+        // a relative path in the marks file is taken from here
+        let path = full_path(&path);
+        let index = match self.buffers.iter().position(|b| full_path(&b.path) == path) {
+            Some(index) => index,
+            None if path.is_file() => {
+                self.open(path.clone());
+                self.buffers.len() - 1
+            },
+            None => {
+                self.message = Some(format!("Can't open {}", path.display()));
+                return false;
+            }
+        };
+        if !self.buffers[index].marks.contains_key(&name) {
+            self.buffers[index].marks.insert(name, (y, x));
+        }
+        let current = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        if current != index {
+            if self.window_tree.find_active_window().unwrap().operator.is_some() {
+                self.message = Some("Can't use an operator across files".to_string());
+                return false;
+            }
+            self.window_tree.find_active_window().unwrap().show_buffer(index as i32);
+        }
+        true
+    }
+
+    // Saves where the buffer's file marks have moved to.
+    pub fn update_file_marks(&mut self, index: usize) {
+        let ref buffer = self.buffers[index];
+        let path = full_path(&buffer.path);
+        let mut changed = false;
+        for (name, &(y, x)) in buffer.marks.iter().filter(|&(name, _)| is_file_mark(*name)) {
+            if self.file_marks.get(name) != Some(&(path.clone(), y, x)) {
+                self.file_marks.insert(*name, (path.clone(), y, x));
+                changed = true;
+            }
+        }
+        if changed {
+            if let Err(e) = save(&self.file_marks) {
+                self.message = Some(format!("Couldn't save marks: {}", e));
+            }
+        }
+    }
+
+    // The buffer with the visual selection, and the selection's start and
+    // end as (y, x): whole lines for V, the corners of the rectangle for
+    // <C-v>.
+    pub fn visual_selection(&mut self) -> Option<(usize, (i32, i32), (i32, i32))> {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        if !window.in_visual_mode() {
+            return None;
+        }
+        let cursor = (window.cursor_y, window.cursor_x);
+        let index = window.buffer_index as usize;
+        window.mark.map(|mark| {
+            match window.mode.as_str() {
+                "visual_line" => (index, (min(mark.0, cursor.0), 0), (max(mark.0, cursor.0), buffer.eol(max(mark.0, cursor.0)))),
+                "visual_block" => (index, (min(mark.0, cursor.0), min(mark.1, cursor.1)), (max(mark.0, cursor.0), max(mark.1, cursor.1))),
+                _ => (index, min(mark, cursor), max(mark, cursor)),
+            }
+        })
+    }
+
+    // Sets '< and '> to a selection from before a command, if the command
+    // ended visual mode. They go in the buffer the selection was in, even if
+    // the command left it.
+    pub fn remember_visual_selection(&mut self, selection: Option<(usize, (i32, i32), (i32, i32))>) {
+        let window = self.window_tree.find_active_window().unwrap();
+        if let Some((index, start, end)) = selection {
+            let ended = !window.in_visual_mode() || window.buffer_index as usize != index;
+            if ended && index < self.buffers.len() {
+                self.buffers[index].marks.insert('<', start);
+                self.buffers[index].marks.insert('>', end);
+            }
+        }
+    }
+
+    // Remembers the cursor's place before a jump, for <C-o> and for the
+    // ' mark.
+    pub fn push_jump(&mut self) {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref mut buffer = self.buffers[window.buffer_index as usize];
+        let here = (window.buffer_index, window.cursor_y, window.cursor_x);
+        buffer.marks.insert('\'', (here.1, here.2));
+        // one place per line
+        window.jumps.retain(|&(index, y, _)| (index, y) != (here.0, here.1));
+        window.jumps.push(here);
+        if window.jumps.len() > JUMP_LIST_SIZE {
+            window.jumps.remove(0);
+        }
+        window.jump_index = window.jumps.len();
+    }
+
+    // <C-o> goes back through the jump list and <C-i> forward again.
+    pub fn follow_jump_list(&mut self, back: bool) {
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let count = window.take_count() as usize;
+        if back && window.jump_index >= window.jumps.len() {
+            // so <C-i> can come back here
+            let here = (window.buffer_index, window.cursor_y, window.cursor_x);
+            window.jumps.retain(|&(index, y, _)| (index, y) != (here.0, here.1));
+            window.jumps.push(here);
+            window.jump_index = window.jumps.len() - 1;
+        }
+        let index = if back {
+            window.jump_index.checked_sub(count)
+        } else {
+            Some(window.jump_index + count).filter(|&i| i < window.jumps.len())
+        };
+        let (buffer_index, y, x) = match index {
+            Some(index) => {
+                window.jump_index = index;
+                window.jumps[index]
+            },
+            None => { return; }
+        };
+        let ref buffer = self.buffers[buffer_index as usize];
+        window.show_buffer(buffer_index);
+        window.cursor_y = max(0, min(y, buffer.eof() - 1));
+        window.cursor_x = max(0, min(x, buffer.eol(window.cursor_y)));
+        window.row = window.cursor_y;
+        window.col = window.cursor_x;
+        window.scroll_to_cursor(window_height);
+    }
+}
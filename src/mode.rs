@@ -1,48 +1,179 @@
 use std::cmp::{min,max};
-use std::process::{Command, Stdio};
-use std::io::{Read, Write};
 use std;
 use editor::Editor;
 use drawer::Drawer;
+use motion;
+use marks;
+use indent;
+use pairs;
+use number;
+use wrap;
+use operator;
+use motion::Target;
+use operator::Region;
 use std::path::Path;
 use ncurses::*;
+use util;
 
 impl Editor {
-    pub fn handle_normal(&mut self, key: &str) {
-        let mut max_x = 0;
-        let mut max_y = 0;
-        getmaxyx(stdscr(), &mut max_y, &mut max_x);
+    pub fn handle_normal(&mut self, command: &str) {
+        if marks::JUMPS.contains(&command) {
+            self.push_jump();
+        }
+        if motion::CURSOR_MOTIONS.contains(&command) {
+            let count = self.window_tree.find_active_window().unwrap().count.take();
+            if let Some(target) = self.motion_target(command, count) {
+                self.finish_motion(target);
+            }
+            return;
+        }
+        match command {
+            "paste" => { self.paste(false); return; },
+            "paste-before" => { self.paste(true); return; },
+            "delete-char" => {
+                let region = {
+                    let window = self.window_tree.find_active_window().unwrap();
+                    let ref buffer = self.buffers[window.buffer_index as usize];
+                    let count = window.take_count();
+                    let length = buffer.lines[window.cursor_y as usize].len() as i32;
+                    if length == 0 {
+                        return;
+                    }
+                    Region {
+                        start: (window.cursor_x, window.cursor_y),
+                        end: (min(window.cursor_x + count, length), window.cursor_y),
+                        linewise: false,
+                    }
+                };
+                self.apply_operator("operator-delete", region);
+                return;
+            },
+            "substitute-char" | "substitute-line" | "change-to-eol" | "delete-to-eol" | "yank-line" => {
+                let (operator, region) = {
+                    let window = self.window_tree.find_active_window().unwrap();
+                    let ref buffer = self.buffers[window.buffer_index as usize];
+                    let count = window.take_count();
+                    let (x, y) = (window.cursor_x, window.cursor_y);
+                    let length = buffer.lines[y as usize].len() as i32;
+                    let end_y = min(buffer.eof() - 1, y + count - 1);
+                    let end_of = |y: i32| (buffer.lines[y as usize].len() as i32, y);
+                    match command {
+                        // s and S change what x and cc would delete
+                        "substitute-char" => ("operator-change", Region { start: (x, y), end: (min(x + count, length), y), linewise: false }),
+                        "substitute-line" => ("operator-change", Region { start: (0, y), end: (0, end_y), linewise: true }),
+                        // C and D to the end of the line, or of the line count - 1 below
+                        "change-to-eol" => ("operator-change", Region { start: (x, y), end: end_of(end_y), linewise: false }),
+                        "delete-to-eol" => ("operator-delete", Region { start: (x, y), end: end_of(end_y), linewise: false }),
+                        _ => ("operator-yank", Region { start: (0, y), end: (0, end_y), linewise: true }),
+                    }
+                };
+                self.apply_operator(operator, region);
+                return;
+            },
+            "comment-line" | "format-line" => {
+                // gcc or gqq the line, 3gcc three
+                let region = {
+                    let window = self.window_tree.find_active_window().unwrap();
+                    let ref buffer = self.buffers[window.buffer_index as usize];
+                    let count = window.take_count();
+                    let y = window.cursor_y;
+                    Region { start: (0, y), end: (0, min(buffer.eof() - 1, y + count - 1)), linewise: true }
+                };
+                self.apply_operator(if command == "comment-line" { "operator-comment" } else { "operator-format" }, region);
+                return;
+            },
+            _ => ()
+        }
+
+        let (max_x, max_y) = util::window_area();
         let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
         let window = self.window_tree.find_active_window().unwrap();
         let ref mut buffer = self.buffers[window.buffer_index as usize];
 
-        match key {
-            "$" => {
+        match command {
+            "move-eol" => {
                 window.cursor_x = buffer.eol(window.cursor_y);
                 window.col = 99999999;
             },
-            "0" => { window.move_bol(); },
-            "A" => {
+            "move-bol" => { window.move_bol(); },
+            "append-eol" => {
                 window.mode = "insert".to_string();
                 while window.cursor_x < buffer.lines[window.cursor_y as usize].len() as i32 {
                     window.move_right();
                 }
             },
-            "d" => { window.mode = "delete".to_string(); },
-            "f" => { window.mode = "find_char".to_string(); },
-            "F" => { window.mode = "find_char_backwards".to_string(); },
-            "G" => {
-                while window.scroll_y < buffer.lines.len() as i32 { //
-                    window.move_down();
-                }
+            "find-char" => { window.mode = "find_char".to_string(); },
+            "set-mark" => { window.mode = "set_mark".to_string(); },
+            "goto-mark" => { window.mode = "goto_mark".to_string(); },
+            "goto-mark-line" => { window.mode = "goto_mark_line".to_string(); },
+            "find-char-backwards" => { window.mode = "find_char_backwards".to_string(); },
+            "till-char" => { window.mode = "till_char".to_string(); },
+            "till-char-backwards" => { window.mode = "till_char_backwards".to_string(); },
+            "goto-last-line" => {
+                // with a count, go to that line instead
+                let line = match window.count.take() {
+                    Some(n) => min(n, buffer.eof()) - 1,
+                    None => buffer.eof() - 1,
+                };
+                window.cursor_y = max(0, line);
+                window.row = window.cursor_y;
+                window.cursor_x = min(buffer.eol(window.cursor_y), window.col);
+                window.scroll_to_cursor(window_height);
             }
-            "h" => {
+            "move-left" => {
                 if window.cursor_x > 0 {
                     window.move_left();
                 }
             },
-            "i" => { window.mode = "insert".to_string(); }
-            "j" => {
+            "insert-mode" => { window.mode = "insert".to_string(); }
+            "insert-bol" => {
+                let y = window.cursor_y;
+                window.cursor_x = match buffer.lines[y as usize].iter().position(|c| !c.ch.is_whitespace()) {
+                    Some(x) => x as i32,
+                    None => buffer.lines[y as usize].len() as i32,
+                };
+                window.col = window.cursor_x;
+                window.mode = "insert".to_string();
+            },
+            "append" => {
+                window.cursor_x = min(window.cursor_x + 1, buffer.lines[window.cursor_y as usize].len() as i32);
+                window.col = window.cursor_x;
+                window.mode = "insert".to_string();
+            },
+            "replace-mode" => {
+                // everything typed over is one undo step, closed at <Escape>
+                self.overtyped.clear();
+                buffer.begin_group();
+                window.mode = "overtype".to_string();
+            },
+            "switch-case" => {
+                // ~ switches the case under the cursor and moves on, 3~ three
+                let count = window.take_count();
+                let (x, y) = (window.cursor_x, window.cursor_y);
+                let end = min(x + count, buffer.lines[y as usize].len() as i32);
+                let text = buffer.text_between((x, y), (end, y));
+                let changed = operator::change_case("operator-switch-case", &text);
+                if changed != text {
+                    buffer.begin_group();
+                    buffer.remove_text(x, y, text.chars().count(), true);
+                    buffer.insert(&changed, x, y, true);
+                    buffer.end_group();
+                }
+                window.cursor_x = min(end, buffer.eol(y));
+                window.col = window.cursor_x;
+            },
+            "increment-number" | "decrement-number" => {
+                let count = window.take_count() as i64;
+                let delta = if command == "increment-number" { count } else { -count };
+                buffer.begin_group();
+                let end = number::increment(buffer, window.cursor_x, window.cursor_y, delta);
+                buffer.end_group();
+                if let Some(x) = end {
+                    window.cursor_x = x;
+                    window.col = x;
+                }
+            },
+            "move-down" => {
                 if window.cursor_y < (buffer.eof() - 1) {
                     window.move_down();
                     if window.cursor_y >= (window.scroll_y + window_height) - 2 {
@@ -52,77 +183,90 @@ impl Editor {
                 }
 
             },
-            "k" => {
+            "move-up" => {
                 if window.cursor_y > 0 {
                     window.move_up();
                     window.cursor_x  = min(buffer.eol(window.cursor_y), window.col);
                 }
             },
-            "l" => {
+            "move-right" => {
                 if window.cursor_x < buffer.eol(window.cursor_y) {
                     window.move_right();
                 }
             },
-            "O" => {
-                buffer.insert_newline(0, window.cursor_y);
-                window.move_bol();
+            "open-line-above" => {
+                let indent = if buffer.settings.auto_indent { indent::indentation(buffer, window.cursor_y) } else { String::new() };
+                buffer.insert(&format!("{}\n", indent), 0, window.cursor_y, true);
+                window.cursor_x = indent.chars().count() as i32;
+                window.col = window.cursor_x;
                 window.mode = "insert".to_string();
             },
-            "o" => {
-                buffer.insert_newline(0, window.cursor_y + 1);
+            "open-line-below" => {
+                let y = window.cursor_y;
+                let length = buffer.lines[y as usize].len() as i32;
+                let indent = if buffer.settings.auto_indent { indent::new_line(buffer, y, length) } else { String::new() };
+                buffer.insert(&format!("\n{}", indent), length, y, true);
                 window.move_down();
-                window.move_bol();
+                window.cursor_x = indent.chars().count() as i32;
+                window.col = window.cursor_x;
                 window.mode = "insert".to_string();
             },
-            "p" => {
-                let p = Command::new("xsel")
-                    .stdin(Stdio::piped())
-                    .stdout(Stdio::piped())
-                    .arg("--clipboard")
-                    .arg("--output")
-                    .spawn()
-                    .expect("Failed to grab from clipboard. Is xsel installed?");
-
-                let mut s = String::new();
-                p.stdout.unwrap().read_to_string(&mut s).expect("Failed to grab from clipboard. Make sure xsel is working properly.");
-                let mut lines = s.split("\n");
-                if lines.clone().count() == 1 {
-                    buffer.insert(lines.next().unwrap(), window.cursor_x, window.cursor_y, true);
-                } else {
-                    let length = lines.clone().count();
-                    for (index, line) in lines.enumerate() {
-                        if index != length - 1 {
-                            buffer.insert_newline(window.cursor_x, window.cursor_y + index as i32);
-                        }
-                        buffer.insert(line, window.cursor_x, window.cursor_y + index as i32, true);
+            "replace-char" => { window.mode = "replace".to_string() },
+            "indent-line" | "dedent-line" => {
+                // to the next or previous multiple of the shift width
+                let shift = buffer.settings.shift_width();
+                let y = window.cursor_y;
+                let width = buffer.indent_width(y);
+                let width = if command == "indent-line" { (width / shift + 1) * shift } else { max(0, (width - 1) / shift * shift) };
+                buffer.begin_group();
+                let added = buffer.set_indent_width(y, width);
+                buffer.end_group();
+                window.cursor_x = max(0, window.cursor_x + added);
+                window.col = window.cursor_x;
+            },
+            "undo" => {
+                if let Some((x, y)) = buffer.undo() {
+                    window.cursor_y = min(y, buffer.eof() - 1);
+                    window.row = window.cursor_y;
+                    window.cursor_x = min(x, buffer.eol(window.cursor_y));
+                    window.col = window.cursor_x;
+                    if window.cursor_y < window.scroll_y {
+                        window.scroll_y = window.cursor_y;
                     }
                 }
             },
-            "r" => { window.mode = "replace".to_string() },
-            "u" => {
-                if let Some(t) = buffer.transactions.pop() {
-                    if t.add {
-                        buffer.remove(t.x, t.y, false);
-                        window.move_left();
-                    } else {
-                        buffer.insert(t.text.as_str(), t.x, t.y, false);
-                        window.move_right();
+            "visual-mode" | "visual-line-mode" | "visual-block-mode" => {
+                let mode = match command {
+                    "visual-mode" => "visual",
+                    "visual-line-mode" => "visual_line",
+                    _ => "visual_block",
+                };
+                if window.mode == mode {
+                    window.mode = "normal".to_string();
+                    window.mark = None;
+                } else {
+                    // switching kinds keeps the selection
+                    if !window.in_visual_mode() {
+                        window.mark = Some((window.cursor_y, window.cursor_x));
                     }
+                    window.mode = mode.to_string();
                 }
             },
-            "v" => {
-                window.mode = "visual".to_string();
-                window.mark = Some((window.cursor_y, window.cursor_x))
-            }
-            "x" => {
-                let x = window.cursor_x;
+            "join-lines" | "join-lines-without-spaces" => {
+                // J joins two lines, 3J three
+                let count = max(2, window.take_count());
                 let y = window.cursor_y;
-                buffer.remove(x, y, true);
-                if x == buffer.eol(y) + 1 {
-                    window.move_left();
+                buffer.begin_group();
+                for _ in 1..count {
+                    if y + 1 < buffer.eof() {
+                        window.cursor_x = buffer.join_line(y, command == "join-lines");
+                    }
                 }
+                buffer.end_group();
+                window.cursor_x = min(window.cursor_x, buffer.eol(y));
+                window.col = window.cursor_x;
             },
-            "<C-b>" => {
+            "page-up" => {
                 for _ in 1..(window_height - 2) {
                     window.move_up();
                     if window.cursor_y < window.scroll_y {
@@ -130,11 +274,12 @@ impl Editor {
                     }
                 }
             },
-            "<C-x>" => {
-                window.mode = "execute".to_string();
+            "find-files" => {
+                window.mode = "find_files".to_string();
+                self.drawer = Some(Drawer::new_find_files(&buffer.path));
             },
-            "<C-c>" => { endwin(); std::process::exit(0); },
-            "<C-f>" => {
+            "quit" => { endwin(); std::process::exit(0); },
+            "page-down" => {
                 for _ in 1..(window_height - 2) {
                     if window.cursor_y < (buffer.eof() - 1) {
                         window.move_down();
@@ -145,39 +290,22 @@ impl Editor {
                     }
                 }
             },
-            // "<C-q>" => { window.destroy_active_window()},
-            "<C-s>" => { buffer.save(); },
-            _ => ()
-        }
-    }
-
-    pub fn handle_delete(&mut self, key: &str) {
-        let ref mut window_tree = self.window_tree;
-        let ref mut window = window_tree.find_active_window().unwrap();
-        let ref mut buffer = self.buffers[window.buffer_index as usize];
-
-        match key {
-            "<Escape>" => {
-                window.mode = "normal".to_string();
-                window.move_left();
-            },
-            "d" => {
-                let row = window.cursor_y;
-                buffer.remove_line(row as usize);
-                window.cursor_x  = min(buffer.eol(window.cursor_y), window.cursor_x);
-                window.mode = "normal".to_string();
-            },
             _ => ()
         }
     }
 
     pub fn handle_insert(&mut self, key: &str) {
+        if key == "<Escape>" {
+            self.finish_block_insert();
+        }
         let ref mut window_tree = self.window_tree;
         let ref mut window = window_tree.find_active_window().unwrap();
         let ref mut buffer = self.buffers[window.buffer_index as usize];
+        let close = pairs::closer_for(buffer, key, window.cursor_x, window.cursor_y);
 
         match key {
             "<Escape>" => {
+                buffer.marks.insert('^', (window.cursor_y, window.cursor_x));
                 window.mode = "normal".to_string();
                 window.move_left();
             },
@@ -195,85 +323,108 @@ impl Editor {
                 } else {
                     window.move_left();
                 }
-                match buffer.char_at(x, y) {
-                    Some(ch1) => {
-                        match buffer.char_at(x - 1, y) {
-                            Some (ch2) => {
-                                match (ch2, ch1) {
-                                    ('"', '"') |
-                                    ('\'', '\'') |
-                                    ('(', ')') |
-                                    ('{', '}') |
-                                    ('[', ']') => { buffer.remove(x, y, true); },
-                                    (_, _) => ()
-                                }
-                            },
-                            None => ()
-                        }
-                    },
-                    None => ()
+                if pairs::around(buffer, x, y) {
+                    buffer.remove(x, y, true);
                 }
 
                 buffer.remove(x - 1, y, true);
             },
             "<Enter>" => {
-                buffer.insert_newline(window.cursor_x, window.cursor_y);
+                let (x, y) = (window.cursor_x, window.cursor_y);
+                if !buffer.settings.auto_indent {
+                    buffer.insert_newline(x, y, true);
+                    window.move_down();
+                    window.move_bol();
+                    return;
+                }
+                let indent = indent::new_line(buffer, y, x);
+                // an indented line left empty loses its indentation
+                let blank = buffer.lines[y as usize].iter().take(x as usize).all(|c| c.ch == ' ' || c.ch == '\t');
+                let x = if blank {
+                    buffer.remove_text(0, y, x as usize, true);
+                    0
+                } else {
+                    x
+                };
+                buffer.insert(&format!("\n{}", indent), x, y, true);
                 window.move_down();
-                window.move_bol();
+                window.cursor_x = indent.chars().count() as i32;
+                window.col = window.cursor_x;
+                // between a pair of brackets the closer gets a line of its own
+                let open = if x > 0 { buffer.char_at(x - 1, y) } else { None };
+                let close = buffer.char_at(window.cursor_x, y + 1);
+                if open.and_then(indent::closer).is_some() && open.and_then(indent::closer) == close {
+                    let outer = indent::indentation(buffer, y);
+                    buffer.insert(&format!("\n{}", outer), window.cursor_x, y + 1, true);
+                }
             },
-            "\"" | "\'" => {
-                buffer.insert(key, window.cursor_x, window.cursor_y, true);
-                window.move_right();
-                buffer.insert(key, window.cursor_x, window.cursor_y, true);
+            "<Tab>" => {
+                if buffer.settings.expand_tab {
+                    let tab_width = buffer.settings.tab_width;
+                    let n = tab_width - buffer.display_col(window.cursor_x, window.cursor_y) % tab_width;
+                    buffer.insert((0..n).map(|_| " ").collect::<String>().as_str(), window.cursor_x, window.cursor_y, true);
+                    for _ in 0..n {
+                        window.move_right();
+                    }
+                } else {
+                    buffer.insert("\t", window.cursor_x, window.cursor_y, true);
+                    window.move_right();
+                }
             },
-            "(" => {
-                buffer.insert("(", window.cursor_x, window.cursor_y, true);
+            _ if pairs::skips_over(buffer, key, window.cursor_x, window.cursor_y) => {
                 window.move_right();
-                buffer.insert(")", window.cursor_x, window.cursor_y, true);
             },
-            "{" => {
-                buffer.insert("{", window.cursor_x, window.cursor_y, true);
+            _ if close.is_some() => {
+                buffer.insert(key, window.cursor_x, window.cursor_y, true);
                 window.move_right();
-                buffer.insert("}", window.cursor_x, window.cursor_y, true);
+                buffer.insert(&close.unwrap().to_string(), window.cursor_x, window.cursor_y, true);
             },
-            "[" => {
-                buffer.insert("[", window.cursor_x, window.cursor_y, true);
+            ")" | "]" | "}" if buffer.settings.auto_indent => {
+                buffer.insert(key, window.cursor_x, window.cursor_y, true);
+                window.cursor_x = indent::align_closer(buffer, window.cursor_x, window.cursor_y);
                 window.move_right();
-                buffer.insert("]", window.cursor_x, window.cursor_y, true);
             },
             _ => {
+                // the character and any line break it causes undo together
+                buffer.begin_group();
                 buffer.insert(key, window.cursor_x, window.cursor_y, true);
                 window.move_right();
+                if key.chars().count() == 1 && !key.chars().all(|c| c.is_whitespace()) {
+                    if let Some((x, y)) = wrap::auto_wrap(buffer, window.cursor_x, window.cursor_y) {
+                        window.cursor_x = x;
+                        window.cursor_y = y;
+                        window.col = x;
+                        window.row = y;
+                    }
+                }
+                buffer.end_group();
             }
         }
     }
 
-    pub fn handle_find_char(&mut self, key: &str) {
-        let ref mut window_tree = self.window_tree;
-        let ref mut window = window_tree.find_active_window().unwrap();
-        let ref mut buffer = self.buffers[window.buffer_index as usize];
-
-        match key {
-            "<Escape>" => {
-                window.mode = "normal".to_string();
+    // f, F, t and T, once the character is typed.
+    pub fn handle_find_char(&mut self, key: &str, forward: bool, till: bool) {
+        let target = {
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref buffer = self.buffers[window.buffer_index as usize];
+            if key == "<Escape>" {
+                window.cancel_operator();
                 window.move_left();
-            },
-            _ => {
-                let y = window.cursor_y as usize;
-                let x = window.cursor_x as usize;
-                match buffer.lines[y].iter().skip(x + 1).position(|c| char::to_string(&c.ch).as_str() == key) {
-                    Some(i) => {
-                        window.cursor_x += (i + 1) as i32;
-                        window.col += (i + 1) as i32;
-                    },
-                    _ => ()
-                }
-                window.mode = "normal".to_string();
+                return;
             }
+            window.mode = "normal".to_string();
+            let count = window.take_count();
+            motion::find_char(buffer, window.cursor_x, window.cursor_y, key, count, forward, till).map(|x| {
+                Target { x: x, y: window.cursor_y, linewise: false, inclusive: forward }
+            })
+        };
+        match target {
+            Some(target) => { self.finish_motion(target); },
+            None => { self.window_tree.find_active_window().unwrap().cancel_operator(); }
         }
     }
 
-    pub fn handle_find_char_backwards(&mut self, key: &str) {
+    pub fn handle_replace(&mut self, key: &str) {
         let ref mut window_tree = self.window_tree;
         let ref mut window = window_tree.find_active_window().unwrap();
         let ref mut buffer = self.buffers[window.buffer_index as usize];
@@ -284,133 +435,105 @@ impl Editor {
                 window.move_left();
             },
             _ => {
-                let y = window.cursor_y as usize;
-                let x = window.cursor_x as usize;
-                match buffer.lines[y].iter().rev().skip(buffer.lines[y].len() - x).position(|c| char::to_string(&c.ch).as_str() == key) {
-                    Some(i) => {
-                        window.cursor_x -= (i + 1) as i32;
-                        window.col -= (i + 1) as i32;
-                    },
-                    _ => ()
+                let count = window.take_count();
+                let x = window.cursor_x;
+                let y = window.cursor_y;
+                if x + count <= buffer.lines[y as usize].len() as i32 {
+                    buffer.begin_group();
+                    for i in 0..count {
+                        buffer.remove(x + i, y, true);
+                        buffer.insert(key, x + i, y, true);
+                    }
+                    buffer.end_group();
+                    window.cursor_x = x + count - 1;
+                    window.col = window.cursor_x;
                 }
                 window.mode = "normal".to_string();
-            }
+            },
         }
     }
 
-    pub fn handle_replace(&mut self, key: &str) {
-        let ref mut window_tree = self.window_tree;
-        let ref mut window = window_tree.find_active_window().unwrap();
+    // R types over the text, and backspace puts back what was there.
+    pub fn handle_overtype(&mut self, key: &str) {
+        let window = self.window_tree.find_active_window().unwrap();
         let ref mut buffer = self.buffers[window.buffer_index as usize];
-
+        let (x, y) = (window.cursor_x, window.cursor_y);
         match key {
             "<Escape>" => {
+                buffer.end_group();
                 window.mode = "normal".to_string();
                 window.move_left();
             },
-            _ => {
-                let x = window.cursor_x;
-                let y = window.cursor_y;
-                buffer.remove(x, y, true);
-                buffer.insert(key, x, y, true);
-                window.mode = "normal".to_string();
+            "<DEL>" | "<Backspace>" => {
+                if x == 0 {
+                    return;
+                }
+                window.move_left();
+                // only what R typed over goes back; earlier text is just passed
+                if let Some(original) = self.overtyped.pop() {
+                    buffer.remove(x - 1, y, true);
+                    if let Some(ch) = original {
+                        buffer.insert(&ch.to_string(), x - 1, y, true);
+                    }
+                }
             },
-        }
-    }
-
-    pub fn handle_execute(&mut self, key: &str) {
-        let ref mut window_tree = self.window_tree;
-        let ref mut window = window_tree.find_active_window().unwrap();
-        let ref mut buffer = self.buffers[window.buffer_index as usize];
-
-        match key {
-            "<C-f>" => {
-                window.mode = "find_files".to_string();
-                self.drawer = Some(Drawer::new_find_files(&buffer.path));
+            "<Enter>" => {
+                buffer.insert_newline(x, y, true);
+                window.move_down();
+                window.move_bol();
+                self.overtyped.clear();
             },
-            _ => {
-                window.mode = "normal".to_string();
-            }
+            _ if key.chars().count() == 1 => {
+                let original = buffer.lines[y as usize].get(x as usize).map(|c| c.ch);
+                if original.is_some() {
+                    buffer.remove(x, y, true);
+                }
+                buffer.insert(key, x, y, true);
+                window.move_right();
+                self.overtyped.push(original);
+            },
+            _ => ()
         }
     }
 
-    pub fn handle_visual(&mut self, key: &str) {
-        match key {
-            "<Escape>" => {
-                let window = self.window_tree.find_active_window().unwrap();
-                window.mode = "normal".to_string();
-                window.mark = None;
-            },
-            "d" | "x" => {
+    pub fn handle_visual(&mut self, command: &str) {
+        match command {
+            "join-lines" | "join-lines-without-spaces" => {
                 let window = self.window_tree.find_active_window().unwrap();
                 let ref mut buffer = self.buffers[window.buffer_index as usize];
-                if let Some(((mut x, mut y), (endx, endy))) = window.calc_mark_region() {
-                    while y > endy || x >= endx {
-                        let length;
-                        if y != endy {
-                            length = (max(1, buffer.lines[(y - 1) as usize].len()) - 1) as i32;
-                        } else {
-                            length = 0;
-                        }
-                        buffer.remove(x, y, true);
-                        if x == -1 && y != endy {
-                            y -= 1;
-                            x = length;
-                        } else {
-                            x -= 1;
+                if let Some((mark_y, _)) = window.mark.take() {
+                    let y = min(mark_y, window.cursor_y);
+                    let n = max(1, (mark_y - window.cursor_y).abs());
+                    buffer.begin_group();
+                    for _ in 0..n {
+                        if y + 1 < buffer.eof() {
+                            window.cursor_x = buffer.join_line(y, command == "join-lines");
                         }
                     }
-
-                    window.cursor_x = max(0, x);
-                    window.col = max(0, x);
-                    window.cursor_y = max(0, y);
-                    window.row = max(0, y);
-                    window.mode = "normal".to_string();
-                    window.mark = None;
+                    buffer.end_group();
+                    window.cursor_y = y;
+                    window.cursor_x = min(window.cursor_x, buffer.eol(y));
+                    window.col = window.cursor_x;
+                    window.row = y;
                 }
+                window.mode = "normal".to_string();
             },
-            "y" => {
-                let window = self.window_tree.find_active_window().unwrap();
-                let ref mut buffer = self.buffers[window.buffer_index as usize];
-                if let Some(((mut x, mut y), (endx, endy))) = window.calc_mark_region() {
-                    let mut region = String::new();
-                    while y > endy || x >= endx {
-                        let length;
-                        if y != endy {
-                            length = (max(1, buffer.lines[(y - 1) as usize].len()) - 1) as i32;
-                        } else {
-                            length = 0;
-                        }
-                        let mut new;
-                        match buffer.char_at(x, y) {
-                            Some(ch) => { new = ch.to_string(); },
-                            None => { new = "\n".to_string(); }
-                        }
-                        new.push_str(region.as_str());
-                        region = new;
-                        if x == -1 && y != endy {
-                            y -= 1;
-                            x = length;
-                        } else {
-                            x -= 1;
-                        }
-                    }
-
-                    let mut p = Command::new("xsel")
-                        .arg("--clipboard")
-                        .arg("--input")
-                        .stdin(Stdio::piped())
-                        .stdout(Stdio::piped())
-                        .spawn()
-                        .ok().expect("Faled to set clipborad. Is xsel installed?");
-
-                    window.mark = None;
-                    window.mode = "normal".to_string();
-
-                    p.stdin.as_mut().unwrap().write_all(region.as_bytes()).expect("Failed to set clipboard. Make sure xsel is working properly.");
+            "block-insert" | "block-append" | "open-rectangle" | "string-rectangle" => {
+                let block = match self.visual_block() {
+                    Some(block) => block,
+                    None => { return; }
+                };
+                match command {
+                    "block-insert" => { self.start_block_insert(block, false); },
+                    "block-append" => { self.start_block_insert(block, true); },
+                    "open-rectangle" => { self.open_rectangle(block); },
+                    _ => { self.start_string_rectangle(block); }
                 }
             },
-            _ => { self.handle_normal(key); }
+            "wrap-selection" => { self.start_wrap_selection(); },
+            "delete-region" => { self.start_operator("operator-delete"); },
+            "yank-region" => { self.start_operator("operator-yank"); },
+            _ => { self.handle_normal(command); }
         }
     }
 
@@ -429,7 +552,7 @@ impl Editor {
                 let ref value = p.join(self.drawer.as_ref().unwrap().lines[self.drawer.as_ref().unwrap().active_line_index as usize].as_str());
                 self.open(Path::new(&value).to_path_buf());
                 let ref mut active = self.window_tree.find_active_window().unwrap();
-                active.buffer_index = (self.buffers.len() - 1) as i32;
+                active.show_buffer((self.buffers.len() - 1) as i32);
                 active.mode = "normal".to_string();
             },
             "<C-n>" => {
@@ -0,0 +1,455 @@
+use std::cmp::{min, max};
+
+use buffer::Buffer;
+use editor::Editor;
+use syntax;
+use util;
+
+// Where a motion ends up, and how much of the text it covers when an
+// operator is waiting for it.
+#[derive(Clone, Copy)]
+pub struct Target {
+    pub x: i32,
+    pub y: i32,
+    pub linewise: bool,
+    pub inclusive: bool,
+}
+
+impl Target {
+    fn exclusive(x: i32, y: i32) -> Target {
+        Target { x: x, y: y, linewise: false, inclusive: false }
+    }
+
+    fn inclusive(x: i32, y: i32) -> Target {
+        Target { x: x, y: y, linewise: false, inclusive: true }
+    }
+
+    fn linewise(x: i32, y: i32) -> Target {
+        Target { x: x, y: y, linewise: true, inclusive: false }
+    }
+}
+
+// Motions that normal mode moves the cursor with through `motion_target`.
+// The older ones have their own arms in `handle_normal`.
+pub static CURSOR_MOTIONS: &'static [&'static str] = &[
+    "goto-first-line",
+    "match-bracket",
+    "move-big-word-backward",
+    "move-big-word-end",
+    "move-big-word-forward",
+    "move-paragraph-backward",
+    "move-paragraph-forward",
+    "move-sentence-backward",
+    "move-sentence-forward",
+    "move-window-bottom",
+    "move-window-middle",
+    "move-window-top",
+    "move-word-backward",
+    "move-word-end",
+    "move-word-forward",
+    "search-next",
+    "search-previous",
+    "search-word-backward",
+    "search-word-forward",
+];
+
+pub const BLANK: u8 = 0;
+const WORD: u8 = 1;
+const PUNCTUATION: u8 = 2;
+
+// A big word (W, B, E) is anything between blanks; a word is a run of word
+// characters or a run of other non-blanks.
+pub fn class_at(buffer: &Buffer, (x, y): (i32, i32), big: bool) -> u8 {
+    match buffer.lines[y as usize].get(x as usize) {
+        Some(cell) if cell.ch.is_whitespace() => BLANK,
+        Some(_) if big => WORD,
+        Some(cell) if is_word_char(buffer, cell.ch) => WORD,
+        Some(_) => PUNCTUATION,
+        None => BLANK,
+    }
+}
+
+pub fn is_word_char(buffer: &Buffer, ch: char) -> bool {
+    ch.is_alphanumeric() || buffer.settings.word_chars.contains(ch)
+}
+
+fn is_empty_line(buffer: &Buffer, y: i32) -> bool {
+    buffer.lines[y as usize].len() == 0
+}
+
+// The next character position, stepping onto the following line at the end
+// of one. Empty lines are a position of their own.
+pub fn next_pos(buffer: &Buffer, (x, y): (i32, i32)) -> Option<(i32, i32)> {
+    if x + 1 < buffer.lines[y as usize].len() as i32 {
+        Some((x + 1, y))
+    } else if y + 1 < buffer.eof() {
+        Some((0, y + 1))
+    } else {
+        None
+    }
+}
+
+pub fn prev_pos(buffer: &Buffer, (x, y): (i32, i32)) -> Option<(i32, i32)> {
+    if x > 0 {
+        Some((x - 1, y))
+    } else if y > 0 {
+        Some((buffer.eol(y - 1), y - 1))
+    } else {
+        None
+    }
+}
+
+fn end_of_line(buffer: &Buffer, y: i32) -> (i32, i32) {
+    (buffer.lines[y as usize].len() as i32, y)
+}
+
+pub fn word_forward(buffer: &Buffer, x: i32, y: i32, big: bool) -> (i32, i32) {
+    let mut pos = (x, y);
+    let start = class_at(buffer, pos, big);
+
+    // the rest of the current word
+    if start != BLANK {
+        loop {
+            match next_pos(buffer, pos) {
+                Some(p) => {
+                    let same_line = p.1 == pos.1;
+                    pos = p;
+                    if !same_line || class_at(buffer, pos, big) != start { break; }
+                },
+                None => { return end_of_line(buffer, pos.1); }
+            }
+        }
+    } else {
+        match next_pos(buffer, pos) {
+            Some(p) => { pos = p; },
+            None => { return end_of_line(buffer, pos.1); }
+        }
+    }
+
+    // then any blanks, but an empty line counts as a word
+    while class_at(buffer, pos, big) == BLANK && !is_empty_line(buffer, pos.1) {
+        match next_pos(buffer, pos) {
+            Some(p) => { pos = p; },
+            None => { return end_of_line(buffer, pos.1); }
+        }
+    }
+    pos
+}
+
+pub fn word_backward(buffer: &Buffer, x: i32, y: i32, big: bool) -> (i32, i32) {
+    let mut pos = match prev_pos(buffer, (x, y)) {
+        Some(p) => p,
+        None => { return (x, y); }
+    };
+    while class_at(buffer, pos, big) == BLANK && !is_empty_line(buffer, pos.1) {
+        match prev_pos(buffer, pos) {
+            Some(p) => { pos = p; },
+            None => { return pos; }
+        }
+    }
+    let class = class_at(buffer, pos, big);
+    if class != BLANK {
+        while let Some(p) = prev_pos(buffer, pos) {
+            if p.1 != pos.1 || class_at(buffer, p, big) != class { break; }
+            pos = p;
+        }
+    }
+    pos
+}
+
+pub fn word_end(buffer: &Buffer, x: i32, y: i32, big: bool) -> (i32, i32) {
+    let mut pos = match next_pos(buffer, (x, y)) {
+        Some(p) => p,
+        None => { return (x, y); }
+    };
+    while class_at(buffer, pos, big) == BLANK {
+        match next_pos(buffer, pos) {
+            Some(p) => { pos = p; },
+            None => { return pos; }
+        }
+    }
+    let class = class_at(buffer, pos, big);
+    while let Some(p) = next_pos(buffer, pos) {
+        if p.1 != pos.1 || class_at(buffer, p, big) != class { break; }
+        pos = p;
+    }
+    pos
+}
+
+// The next empty line after the current paragraph, or the end of the buffer.
+pub fn paragraph_forward(buffer: &Buffer, y: i32) -> (i32, i32) {
+    let mut y = y;
+    while y + 1 < buffer.eof() && is_empty_line(buffer, y) {
+        y += 1;
+    }
+    while y + 1 < buffer.eof() {
+        y += 1;
+        if is_empty_line(buffer, y) {
+            return (0, y);
+        }
+    }
+    (buffer.eol(y), y)
+}
+
+pub fn paragraph_backward(buffer: &Buffer, y: i32) -> (i32, i32) {
+    let mut y = y;
+    while y > 0 && is_empty_line(buffer, y) {
+        y -= 1;
+    }
+    while y > 0 {
+        y -= 1;
+        if is_empty_line(buffer, y) {
+            return (0, y);
+        }
+    }
+    (0, 0)
+}
+
+// A sentence starts after a `.`, `!` or `?` (and any closing brackets or
+// quotes) followed by a blank, and at paragraph boundaries.
+fn is_sentence_start(buffer: &Buffer, pos: (i32, i32)) -> bool {
+    if is_empty_line(buffer, pos.1) {
+        return true;
+    }
+    if class_at(buffer, pos, true) == BLANK {
+        return false;
+    }
+    let mut saw_blank = false;
+    let mut q = pos;
+    loop {
+        match prev_pos(buffer, q) {
+            Some(p) => {
+                if p.1 != q.1 {
+                    saw_blank = true;
+                }
+                q = p;
+            },
+            None => { return true; }
+        }
+        if is_empty_line(buffer, q.1) {
+            return true;
+        }
+        if class_at(buffer, q, true) != BLANK { break; }
+        saw_blank = true;
+    }
+    if !saw_blank {
+        return false;
+    }
+    let ref line = buffer.lines[q.1 as usize];
+    let mut x = q.0;
+    while x > 0 && ")]\"'".contains(line[x as usize].ch) {
+        x -= 1;
+    }
+    ".!?".contains(line[x as usize].ch)
+}
+
+pub fn sentence_forward(buffer: &Buffer, x: i32, y: i32) -> (i32, i32) {
+    let mut pos = (x, y);
+    while let Some(p) = next_pos(buffer, pos) {
+        pos = p;
+        if is_sentence_start(buffer, pos) {
+            return pos;
+        }
+    }
+    end_of_line(buffer, pos.1)
+}
+
+pub fn sentence_backward(buffer: &Buffer, x: i32, y: i32) -> (i32, i32) {
+    let mut pos = (x, y);
+    while let Some(p) = prev_pos(buffer, pos) {
+        pos = p;
+        if is_sentence_start(buffer, pos) {
+            return pos;
+        }
+    }
+    pos
+}
+
+// Whether the character at `pos` is in a string or comment, given the
+// result of `syntax::strings_and_comments`.
+pub fn is_quoted(quoted: &Option<Vec<Vec<bool>>>, (x, y): (i32, i32)) -> bool {
+    quoted.as_ref().map(|q| q[y as usize].get(x as usize).cloned().unwrap_or(false)).unwrap_or(false)
+}
+
+// Scans from `pos` for the first `open` (backwards) or `close` (forwards)
+// that isn't balanced by the other, skipping brackets whose quotedness
+// differs from `inside`.
+pub fn find_bracket(buffer: &Buffer, pos: (i32, i32), open: char, close: char, forward: bool,
+                    quoted: &Option<Vec<Vec<bool>>>, inside: bool) -> Option<(i32, i32)> {
+    let (nested, wanted) = if forward { (open, close) } else { (close, open) };
+    let mut depth = 0;
+    let mut pos = pos;
+    loop {
+        let next = if forward { next_pos(buffer, pos) } else { prev_pos(buffer, pos) };
+        pos = match next {
+            Some(p) => p,
+            None => { return None; }
+        };
+        let c = match buffer.lines[pos.1 as usize].get(pos.0 as usize) {
+            Some(cell) => cell.ch,
+            None => { continue; }
+        };
+        if (c != nested && c != wanted) || is_quoted(quoted, pos) != inside {
+            continue;
+        }
+        if c == nested {
+            depth += 1;
+        } else if depth == 0 {
+            return Some(pos);
+        } else {
+            depth -= 1;
+        }
+    }
+}
+
+// The bracket matching the first one at or after `x` on the line. Brackets
+// in strings and comments only match each other.
+pub fn match_bracket(buffer: &Buffer, x: i32, y: i32) -> Option<(i32, i32)> {
+    let pairs = [('(', ')'), ('[', ']'), ('{', '}')];
+    let ref line = buffer.lines[y as usize];
+    let start = match line.iter().skip(max(0, x) as usize).position(|c| "()[]{}".contains(c.ch)) {
+        Some(i) => x + i as i32,
+        None => { return None; }
+    };
+    let ch = line[start as usize].ch;
+    let (open, close) = *pairs.iter().find(|&&(o, c)| o == ch || c == ch).unwrap();
+    let quoted = syntax::strings_and_comments(buffer);
+    let inside = is_quoted(&quoted, (start, y));
+    find_bracket(buffer, (start, y), open, close, ch == open, &quoted, inside)
+}
+
+// The column of the `count`th `ch` on the line, searching from `x`. With
+// `till` it stops one short of the character.
+pub fn find_char(buffer: &Buffer, x: i32, y: i32, ch: &str, count: i32, forward: bool, till: bool) -> Option<i32> {
+    let ref line = buffer.lines[y as usize];
+    let matches = |i: i32| line[i as usize].ch.to_string().as_str() == ch;
+    let mut pos = x;
+    for n in 0..count {
+        // a repeated till mustn't stop on the character it's already next to
+        let skip = if till && n == 0 { 2 } else { 1 };
+        let mut i = if forward { pos + skip } else { pos - skip };
+        loop {
+            if i < 0 || i >= line.len() as i32 {
+                return None;
+            }
+            if matches(i) { break; }
+            i = if forward { i + 1 } else { i - 1 };
+        }
+        pos = i;
+    }
+    if till {
+        pos = if forward { pos - 1 } else { pos + 1 };
+    }
+    Some(pos)
+}
+
+// Applies `step` to `pos` `n` times.
+fn repeat<F>(pos: (i32, i32), n: i32, step: F) -> (i32, i32) where F: Fn((i32, i32)) -> (i32, i32) {
+    let mut pos = pos;
+    for _ in 0..n {
+        pos = step(pos);
+    }
+    pos
+}
+
+impl Editor {
+    // Where motion `name` goes from the cursor, or None if it isn't a motion.
+    pub fn motion_target(&mut self, name: &str, count: Option<i32>) -> Option<Target> {
+        match name {
+            "search-next" | "search-previous" | "search-word-forward" | "search-word-backward" => {
+                return self.search_target(name, count);
+            },
+            _ => ()
+        }
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        let x = window.cursor_x;
+        let y = window.cursor_y;
+        let n = count.unwrap_or(1);
+        let last = buffer.eof() - 1;
+        // the last line shown in the window
+        let bottom = max(window.scroll_y, min(last, window.scroll_y + window_height - 3));
+
+        let to_line = |y: i32| Target::linewise(buffer.first_non_blank(y), y);
+
+        let target = match name {
+            "move-left" => Target::exclusive(max(0, x - n), y),
+            "move-right" => Target::exclusive(min(buffer.lines[y as usize].len() as i32, x + n), y),
+            "move-up" => Target::linewise(x, max(0, y - n)),
+            "move-down" => Target::linewise(x, min(last, y + n)),
+            "move-bol" => Target::exclusive(0, y),
+            "move-eol" => {
+                let end = min(last, y + n - 1);
+                Target::inclusive(buffer.eol(end), end)
+            },
+            "goto-first-line" => Target::linewise(x, min(last, max(0, count.unwrap_or(1) - 1))),
+            "goto-last-line" => Target::linewise(x, min(last, max(0, count.unwrap_or(last + 1) - 1))),
+            "move-window-top" => to_line(min(bottom, window.scroll_y + n - 1)),
+            "move-window-middle" => to_line(window.scroll_y + (bottom - window.scroll_y) / 2),
+            "move-window-bottom" => to_line(max(window.scroll_y, bottom - (n - 1))),
+            "move-word-forward" | "move-big-word-forward" => {
+                let big = name == "move-big-word-forward";
+                let (x, y) = repeat((x, y), n, |(x, y)| word_forward(buffer, x, y, big));
+                Target::exclusive(x, y)
+            },
+            "move-word-backward" | "move-big-word-backward" => {
+                let big = name == "move-big-word-backward";
+                let (x, y) = repeat((x, y), n, |(x, y)| word_backward(buffer, x, y, big));
+                Target::exclusive(x, y)
+            },
+            "move-word-end" | "move-big-word-end" => {
+                let big = name == "move-big-word-end";
+                let (x, y) = repeat((x, y), n, |(x, y)| word_end(buffer, x, y, big));
+                Target::inclusive(x, y)
+            },
+            "move-paragraph-forward" => {
+                let (x, y) = repeat((x, y), n, |(_, y)| paragraph_forward(buffer, y));
+                Target::exclusive(x, y)
+            },
+            "move-paragraph-backward" => {
+                let (x, y) = repeat((x, y), n, |(_, y)| paragraph_backward(buffer, y));
+                Target::exclusive(x, y)
+            },
+            "move-sentence-forward" => {
+                let (x, y) = repeat((x, y), n, |(x, y)| sentence_forward(buffer, x, y));
+                Target::exclusive(x, y)
+            },
+            "move-sentence-backward" => {
+                let (x, y) = repeat((x, y), n, |(x, y)| sentence_backward(buffer, x, y));
+                Target::exclusive(x, y)
+            },
+            "match-bracket" => {
+                match match_bracket(buffer, x, y) {
+                    Some((x, y)) => Target::inclusive(x, y),
+                    None => { return None; }
+                }
+            },
+            _ => { return None; }
+        };
+        Some(target)
+    }
+
+    // Hands the target to a waiting operator, or moves the cursor there.
+    pub fn finish_motion(&mut self, target: Target) {
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let operator = self.window_tree.find_active_window().unwrap().operator.take();
+        match operator {
+            Some(operator) => {
+                let region = self.region_to(target);
+                self.apply_operator(&operator, region);
+            },
+            None => {
+                let window = self.window_tree.find_active_window().unwrap();
+                let ref buffer = self.buffers[window.buffer_index as usize];
+                window.cursor_y = max(0, min(target.y, buffer.eof() - 1));
+                window.row = window.cursor_y;
+                window.cursor_x = max(0, min(target.x, buffer.eol(window.cursor_y)));
+                window.col = window.cursor_x;
+                window.scroll_to_cursor(window_height);
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use buffer::Buffer;
+
+// A number on a line: where it starts (at its sign or 0x/0b prefix), where
+// it ends, and its radix.
+struct Number {
+    start: usize,
+    end: usize,
+    radix: u32,
+}
+
+// The numbers on a line, left to right.
+fn numbers(line: &[char]) -> Vec<Number> {
+    let mut result = vec![];
+    let mut i = 0;
+    while i < line.len() {
+        if !line[i].is_digit(10) {
+            i += 1;
+            continue;
+        }
+        let prefixed = |radix: u32| line.get(i + 2).map(|c| c.is_digit(radix)).unwrap_or(false);
+        let radix = match line.get(i + 1) {
+            Some(&'x') | Some(&'X') if line[i] == '0' && prefixed(16) => 16,
+            Some(&'b') | Some(&'B') if line[i] == '0' && prefixed(2) => 2,
+            _ => 10,
+        };
+        let mut start = i;
+        let mut end = if radix == 10 { i } else { i + 2 };
+        while end < line.len() && line[end].is_digit(radix) {
+            end += 1;
+        }
+        // a minus sign counts unless it's joining words, as in `foo-1`
+        if radix == 10 && i > 0 && line[i - 1] == '-' && (i < 2 || !line[i - 2].is_alphanumeric()) {
+            start = i - 1;
+        }
+        result.push(Number { start: start, end: end, radix: radix });
+        i = end;
+    }
+    result
+}
+
+// Adds delta to the number under or after x on line y, keeping a hex or
+// binary number's width and case. Returns where the number now ends.
+pub fn increment(buffer: &mut Buffer, x: i32, y: i32, delta: i64) -> Option<i32> {
+    let line: Vec<char> = buffer.lines[y as usize].iter().map(|c| c.ch).collect();
+    let number = match numbers(&line).into_iter().find(|n| n.end as i32 > x) {
+        Some(number) => number,
+        None => { return None; }
+    };
+    let text: String = line[number.start..number.end].iter().cloned().collect();
+    let replacement = if number.radix == 10 {
+        match text.parse::<i64>() {
+            Ok(value) => value.saturating_add(delta).to_string(),
+            // too big to count with
+            Err(_) => { return None; }
+        }
+    } else {
+        let digits = &text[2..];
+        let value = match u64::from_str_radix(digits, number.radix) {
+            Ok(value) => value.wrapping_add(delta as u64),
+            Err(_) => { return None; }
+        };
+        let width = digits.len();
+        let formatted = match number.radix {
+            16 if digits.chars().any(|c| c.is_ascii_uppercase()) => format!("{:0width$X}", value, width = width),
+            16 => format!("{:0width$x}", value, width = width),
+            _ => format!("{:0width$b}", value, width = width),
+        };
+        format!("{}{}", &text[..2], formatted)
+    };
+    buffer.remove_text(number.start as i32, y, number.end - number.start, true);
+    buffer.insert(&replacement, number.start as i32, y, true);
+    Some(number.start as i32 + replacement.chars().count() as i32 - 1)
+}
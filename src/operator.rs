@@ -0,0 +1,295 @@
+use std::cmp::{min, max};
+
+use editor::Editor;
+use comment;
+use indent;
+use motion::Target;
+use util;
+use wrap;
+
+// The text an operator acts on. `end` is exclusive; a linewise region covers
+// every line from `start` to `end` whatever the columns.
+#[derive(Clone, Copy)]
+pub struct Region {
+    pub start: (i32, i32),
+    pub end: (i32, i32),
+    pub linewise: bool,
+}
+
+pub static OPERATORS: &'static [&'static str] = &[
+    "operator-change",
+    "operator-comment",
+    "operator-dedent",
+    "operator-delete",
+    "operator-format",
+    "operator-indent",
+    "operator-lowercase",
+    "operator-reindent",
+    "operator-switch-case",
+    "operator-uppercase",
+    "operator-yank",
+];
+
+pub fn is_operator(name: &str) -> bool {
+    OPERATORS.contains(&name)
+}
+
+// `text` as gu, gU or g~ leaves it.
+pub fn change_case(name: &str, text: &str) -> String {
+    match name {
+        "operator-lowercase" => text.to_lowercase(),
+        "operator-uppercase" => text.to_uppercase(),
+        _ => text.chars().map(|c| {
+            if c.is_lowercase() { c.to_uppercase().collect::<String>() } else { c.to_lowercase().collect() }
+        }).collect(),
+    }
+}
+
+impl Editor {
+    // Operators wait for a motion in normal mode, act on the selection in
+    // visual mode, and act on whole lines when typed twice (dd, >>, gUgU).
+    pub fn start_operator(&mut self, name: &str) {
+        let mode = self.window_tree.find_active_window().unwrap().mode.clone();
+        match mode.as_str() {
+            "visual" | "visual_line" => {
+                if let Some(region) = self.visual_region() {
+                    self.window_tree.find_active_window().unwrap().mark = None;
+                    self.apply_operator(name, region);
+                }
+            },
+            "visual_block" => {
+                if let Some(block) = self.visual_block() {
+                    self.window_tree.find_active_window().unwrap().mark = None;
+                    self.apply_block_operator(name, block);
+                }
+            },
+            "operator" => {
+                let region = {
+                    let window = self.window_tree.find_active_window().unwrap();
+                    let ref buffer = self.buffers[window.buffer_index as usize];
+                    if window.operator.as_ref().map(|o| o.as_str()) != Some(name) {
+                        window.cancel_operator();
+                        return;
+                    }
+                    window.operator = None;
+                    let count = window.take_operator_count().unwrap_or(1);
+                    let y = window.cursor_y;
+                    Region {
+                        start: (0, y),
+                        end: (0, min(buffer.eof() - 1, y + count - 1)),
+                        linewise: true,
+                    }
+                };
+                self.apply_operator(name, region);
+            },
+            _ => {
+                let window = self.window_tree.find_active_window().unwrap();
+                window.mode = "operator".to_string();
+                window.operator = Some(name.to_string());
+                window.operator_count = window.count.take();
+            }
+        }
+    }
+
+    // A command typed while an operator waits for its motion.
+    pub fn handle_operator(&mut self, name: &str) {
+        match name {
+            "find-char" | "find-char-backwards" | "till-char" | "till-char-backwards" | "goto-mark" | "goto-mark-line" => {
+                {
+                    let window = self.window_tree.find_active_window().unwrap();
+                    window.count = window.take_operator_count();
+                }
+                // switches to the mode that reads the character
+                self.handle_normal(name);
+            },
+            _ => {
+                let (count, change_word) = {
+                    let window = self.window_tree.find_active_window().unwrap();
+                    let ref buffer = self.buffers[window.buffer_index as usize];
+                    let on_word = buffer.lines[window.cursor_y as usize].get(window.cursor_x as usize)
+                        .map(|c| !c.ch.is_whitespace())
+                        .unwrap_or(false);
+                    let change = window.operator.as_ref().map(|o| o == "operator-change").unwrap_or(false);
+                    (window.take_operator_count(), change && on_word)
+                };
+                // like vim, cw changes to the end of the word
+                let name = match name {
+                    "move-word-forward" if change_word => "move-word-end",
+                    "move-big-word-forward" if change_word => "move-big-word-end",
+                    _ => name,
+                };
+                match self.motion_target(name, count) {
+                    Some(target) => {
+                        let target = match name {
+                            "move-word-forward" | "move-big-word-forward" => self.word_motion_end(target),
+                            _ => target,
+                        };
+                        self.finish_motion(target);
+                    },
+                    None => {
+                        self.window_tree.find_active_window().unwrap().cancel_operator();
+                    }
+                }
+            }
+        }
+    }
+
+    // A word motion that crosses into another line stops at the end of the
+    // line the last word was on, so dw doesn't join lines.
+    fn word_motion_end(&mut self, target: Target) -> Target {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        if target.y > window.cursor_y && target.x <= buffer.first_non_blank(target.y) {
+            let y = target.y - 1;
+            let x = buffer.lines[y as usize].len() as i32;
+            if (y, x) > (window.cursor_y, window.cursor_x) {
+                return Target { x: x, y: y, linewise: false, inclusive: false };
+            }
+        }
+        target
+    }
+
+    // The text between the cursor and `target`.
+    pub fn region_to(&mut self, target: Target) -> Region {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        let cursor = (window.cursor_x, window.cursor_y);
+        let to = (target.x, target.y);
+        let (start, end) = if (to.1, to.0) < (cursor.1, cursor.0) { (to, cursor) } else { (cursor, to) };
+        let end = if target.inclusive && !target.linewise {
+            (min(end.0 + 1, buffer.lines[end.1 as usize].len() as i32), end.1)
+        } else {
+            end
+        };
+        Region { start: start, end: end, linewise: target.linewise }
+    }
+
+    // The visual selection, including the character under the cursor and
+    // the line break after it on an empty line. With V, the whole lines.
+    pub fn visual_region(&mut self) -> Option<Region> {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        if window.mode == "visual_line" {
+            return window.mark.map(|(y, _)| Region {
+                start: (0, min(y, window.cursor_y)),
+                end: (0, max(y, window.cursor_y)),
+                linewise: true,
+            });
+        }
+        window.calc_mark_region().map(|((x, y), (start_x, start_y))| {
+            let end = if x < buffer.lines[y as usize].len() as i32 {
+                (x + 1, y)
+            } else if y + 1 < buffer.eof() {
+                (0, y + 1)
+            } else {
+                (x, y)
+            };
+            Region { start: (max(0, start_x), start_y), end: end, linewise: false }
+        })
+    }
+
+    pub fn apply_operator(&mut self, name: &str, region: Region) {
+        match name {
+            "operator-delete" | "operator-change" => { self.store_region(region, false); },
+            "operator-yank" => { self.store_region(region, true); },
+            _ => ()
+        }
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref mut buffer = self.buffers[window.buffer_index as usize];
+        let (x, y) = region.start;
+        let end_y = region.end.1;
+        window.mode = "normal".to_string();
+        window.operator = None;
+
+        buffer.begin_group();
+        match name {
+            "operator-delete" | "operator-change" => {
+                if region.linewise && name == "operator-change" {
+                    for _ in y..end_y {
+                        buffer.remove_line((y + 1) as usize, true);
+                    }
+                    let n = buffer.lines[y as usize].len();
+                    buffer.remove_text(0, y, n, true);
+                    window.cursor_x = 0;
+                    window.cursor_y = y;
+                } else if region.linewise {
+                    for _ in y..(end_y + 1) {
+                        buffer.remove_line(y as usize, true);
+                    }
+                    window.cursor_y = min(y, buffer.eof() - 1);
+                    window.cursor_x = buffer.first_non_blank(window.cursor_y);
+                } else {
+                    let n = buffer.text_between(region.start, region.end).chars().count();
+                    buffer.remove_text(x, y, n, true);
+                    window.cursor_x = x;
+                    window.cursor_y = y;
+                }
+                if name == "operator-change" {
+                    window.mode = "insert".to_string();
+                }
+            },
+            "operator-yank" => {
+                if !region.linewise {
+                    window.cursor_x = x;
+                }
+                window.cursor_y = y;
+            },
+            "operator-indent" | "operator-dedent" => {
+                let shift = buffer.settings.shift_width() * if name == "operator-indent" { 1 } else { -1 };
+                for line in y..(end_y + 1) {
+                    // empty lines stay empty
+                    if buffer.lines[line as usize].len() > 0 {
+                        let width = buffer.indent_width(line);
+                        buffer.set_indent_width(line, width + shift);
+                    }
+                }
+                window.cursor_y = y;
+                window.cursor_x = buffer.first_non_blank(y);
+            },
+            "operator-reindent" => {
+                indent::reindent(buffer, y, end_y);
+                window.cursor_y = y;
+                window.cursor_x = buffer.first_non_blank(y);
+            },
+            "operator-format" => {
+                // gq leaves the cursor on the last line it filled
+                let last = wrap::reflow(buffer, y, end_y);
+                window.cursor_y = last;
+                window.cursor_x = buffer.first_non_blank(last);
+            },
+            "operator-comment" => {
+                if !comment::toggle(buffer, y, end_y) {
+                    self.message = Some("No comment markers for this file".to_string());
+                }
+                window.cursor_y = y;
+                window.cursor_x = buffer.first_non_blank(y);
+            },
+            "operator-lowercase" | "operator-uppercase" | "operator-switch-case" => {
+                let (start, end) = if region.linewise {
+                    ((0, y), (buffer.lines[end_y as usize].len() as i32, end_y))
+                } else {
+                    (region.start, region.end)
+                };
+                let text = buffer.text_between(start, end);
+                let changed = change_case(name, &text);
+                if changed != text {
+                    buffer.remove_text(start.0, start.1, text.chars().count(), true);
+                    buffer.insert(&changed, start.0, start.1, true);
+                }
+                window.cursor_x = start.0;
+                window.cursor_y = start.1;
+            },
+            _ => ()
+        }
+        buffer.end_group();
+
+        if window.mode == "normal" {
+            window.cursor_x = min(window.cursor_x, buffer.eol(window.cursor_y));
+        }
+        window.col = window.cursor_x;
+        window.row = window.cursor_y;
+        window.scroll_to_cursor(window_height);
+    }
+}
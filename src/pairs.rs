@@ -0,0 +1,176 @@
+use std::cmp::min;
+
+use block;
+use buffer::Buffer;
+use editor::Editor;
+use motion;
+use syntax;
+
+// Pairs for languages that don't use the default `()[]{}""''`, each an
+// opener followed by its closer, keyed by syntect syntax name.
+pub static LANGUAGE_PAIRS: &'static [(&'static str, &'static str)] = &[
+    ("Go", "()[]{}\"\"``"),
+    ("Lisp", "()[]{}\"\""),
+    ("Markdown", "()[]{}\"\"``"),
+    ("Rust", "()[]{}\"\""),
+];
+
+pub fn pairs_for(syntax: &str) -> Option<&'static str> {
+    LANGUAGE_PAIRS.iter().find(|&&(name, _)| name == syntax).map(|&(_, pairs)| pairs)
+}
+
+// The closer paired with `open` in `pairs`.
+pub fn closer(pairs: &str, open: char) -> Option<char> {
+    let chars: Vec<char> = pairs.chars().collect();
+    chars.chunks(2).find(|pair| pair.len() == 2 && pair[0] == open).map(|pair| pair[1])
+}
+
+fn is_closer(pairs: &str, close: char) -> bool {
+    pairs.chars().skip(1).step_by(2).any(|c| c == close)
+}
+
+fn single_char(key: &str) -> Option<char> {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(ch), None) => Some(ch),
+        _ => None,
+    }
+}
+
+// Whether typing `key` at x should step over the closer already there.
+pub fn skips_over(buffer: &Buffer, key: &str, x: i32, y: i32) -> bool {
+    if !buffer.settings.auto_pairs {
+        return false;
+    }
+    match single_char(key) {
+        Some(ch) => is_closer(&buffer.settings.pairs, ch) && buffer.lines[y as usize].get(x as usize).map(|c| c.ch) == Some(ch),
+        None => false,
+    }
+}
+
+// The closer to add after `key` typed at x, if it opens a pair. Nothing is
+// paired in front of a word, and quotes aren't paired after a word (so
+// `don't` stays as typed) or in a string or comment.
+pub fn closer_for(buffer: &Buffer, key: &str, x: i32, y: i32) -> Option<char> {
+    if !buffer.settings.auto_pairs {
+        return None;
+    }
+    let open = match single_char(key) {
+        Some(ch) => ch,
+        None => { return None; }
+    };
+    let close = match closer(&buffer.settings.pairs, open) {
+        Some(close) => close,
+        None => { return None; }
+    };
+    let next = buffer.lines[y as usize].get(x as usize).map(|c| c.ch);
+    if next.map(|ch| motion::is_word_char(buffer, ch)).unwrap_or(false) {
+        return None;
+    }
+    if open == close {
+        let previous = if x > 0 { buffer.lines[y as usize].get(x as usize - 1).map(|c| c.ch) } else { None };
+        if previous.map(|ch| motion::is_word_char(buffer, ch)).unwrap_or(false) {
+            return None;
+        }
+        if syntax::in_string_or_comment(buffer, x, y) {
+            return None;
+        }
+    }
+    Some(close)
+}
+
+// Whether the characters either side of x are a pair, for backspace to
+// delete both.
+pub fn around(buffer: &Buffer, x: i32, y: i32) -> bool {
+    let ref line = buffer.lines[y as usize];
+    if !buffer.settings.auto_pairs || x == 0 || x >= line.len() as i32 {
+        return false;
+    }
+    closer(&buffer.settings.pairs, line[x as usize - 1].ch) == Some(line[x as usize].ch)
+}
+
+impl Editor {
+    // S in visual mode, then an opener or closer, puts the pair around the
+    // selection. The openers alone are motions, registers and marks there.
+    pub fn start_wrap_selection(&mut self) {
+        let window = self.window_tree.find_active_window().unwrap();
+        self.wrap_mode = Some(window.mode.clone());
+        window.mode = "wrap_selection".to_string();
+    }
+
+    pub fn handle_wrap_selection(&mut self, key: &str) {
+        let mode = self.wrap_mode.take().unwrap_or("visual".to_string());
+        let (open, close) = {
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref buffer = self.buffers[window.buffer_index as usize];
+            // closers wrap too, as in surround.vim
+            let pair = single_char(key).and_then(|ch| {
+                let chars: Vec<char> = buffer.settings.pairs.chars().collect();
+                chars.chunks(2).find(|pair| pair.len() == 2 && (pair[0] == ch || pair[1] == ch))
+                    .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+            });
+            match pair {
+                Some(pair) => {
+                    window.mode = mode.clone();
+                    pair
+                },
+                None => {
+                    window.mode = "normal".to_string();
+                    window.mark = None;
+                    return;
+                }
+            }
+        };
+        // each line's piece of a block, otherwise the selection as a whole
+        let pieces: Vec<((i32, i32), (i32, i32))> = if mode == "visual_block" {
+            let block = self.visual_block();
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref buffer = self.buffers[window.buffer_index as usize];
+            match block {
+                Some(block) => (block.top..(block.bottom + 1)).map(|y| {
+                    let (start, end) = block::columns(buffer, &block, y);
+                    ((start, y), (end, y))
+                }).filter(|&((start, _), (end, _))| start < end).collect(),
+                None => vec![],
+            }
+        } else {
+            let region = self.visual_region();
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref buffer = self.buffers[window.buffer_index as usize];
+            match region {
+                Some(ref region) if region.linewise => {
+                    let (top, bottom) = (region.start.1, region.end.1);
+                    vec![((buffer.first_non_blank(top), top), (buffer.lines[bottom as usize].len() as i32, bottom))]
+                },
+                Some(region) => {
+                    // a selection running onto the next line ends at this one's end
+                    let end = if region.end.0 == 0 && region.end.1 > region.start.1 {
+                        (buffer.lines[region.end.1 as usize - 1].len() as i32, region.end.1 - 1)
+                    } else {
+                        region.end
+                    };
+                    vec![(region.start, end)]
+                },
+                None => vec![],
+            }
+        };
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref mut buffer = self.buffers[window.buffer_index as usize];
+        window.mode = "normal".to_string();
+        window.mark = None;
+        if pieces.len() == 0 {
+            return;
+        }
+        buffer.begin_group();
+        for &((start_x, start_y), (end_x, end_y)) in pieces.iter().rev() {
+            buffer.insert(&close, end_x, end_y, true);
+            buffer.insert(&open, start_x, start_y, true);
+        }
+        buffer.end_group();
+        let ((x, y), _) = pieces[0];
+        window.cursor_y = y;
+        window.cursor_x = min(x, buffer.eol(y));
+        window.col = window.cursor_x;
+        window.row = y;
+    }
+}
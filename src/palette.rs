@@ -0,0 +1,59 @@
+use command;
+use drawer::Drawer;
+use editor::Editor;
+use text_object;
+
+impl Editor {
+    // M-x: every command with its bindings and description, narrowed down
+    // by typing part of its name.
+    pub fn start_palette(&mut self) {
+        let choices = command::COMMANDS.iter()
+            // text objects only mean something after an operator
+            .filter(|&&(name, _)| !text_object::is_text_object(name) && name != "execute-command")
+            .map(|&(name, description)| {
+                format!("{:26}{:16}{}", name, self.keymap.bindings_for(name).join(" "), description)
+            })
+            .collect();
+        let window = self.window_tree.find_active_window().unwrap();
+        self.palette_mode = Some(window.mode.clone());
+        window.mode = "palette".to_string();
+        self.drawer = Some(Drawer::new_picker("palette", "M-x ", choices));
+    }
+
+    pub fn handle_palette(&mut self, key: &str) {
+        match key {
+            "<Escape>" | "<C-g>" => { self.end_palette(); },
+            "<Enter>" => {
+                let command = {
+                    let drawer = self.drawer.as_ref().unwrap();
+                    drawer.lines.get(drawer.active_line_index as usize)
+                        .and_then(|line| line.split_whitespace().next())
+                        .map(|name| name.to_string())
+                };
+                self.end_palette();
+                if let Some(command) = command {
+                    self.run_command(&command);
+                }
+            },
+            "<C-n>" | "<Down>" | "<Tab>" => {
+                let drawer = self.drawer.as_mut().unwrap();
+                if drawer.lines.len() > 0 {
+                    drawer.next_item();
+                }
+            },
+            "<C-p>" | "<Up>" => {
+                let drawer = self.drawer.as_mut().unwrap();
+                if drawer.lines.len() > 0 {
+                    drawer.prev_item();
+                }
+            },
+            _ => { self.drawer.as_mut().unwrap().handle_key(key); }
+        }
+    }
+
+    fn end_palette(&mut self) {
+        self.drawer = None;
+        let window = self.window_tree.find_active_window().unwrap();
+        window.mode = self.palette_mode.take().unwrap_or("normal".to_string());
+    }
+}
@@ -0,0 +1,312 @@
+use std::char;
+use std::cmp::{min, max};
+use std::collections::HashMap;
+
+use clipboard::Clipboard;
+use editor::{Editor, SOUTH};
+use operator::Region;
+use util;
+
+// How many yanks and deletes the kill ring keeps.
+const KILL_RING_SIZE: usize = 30;
+
+// Text in a register. `kind` is "charwise", "linewise" for whole lines,
+// which end in a newline, or "blockwise" for a rectangle, one line of text
+// per row.
+#[derive(Clone, PartialEq)]
+pub struct Clip {
+    pub text: String,
+    pub kind: &'static str,
+}
+
+// Where the last paste went, so yank-pop can swap it for an older kill.
+pub struct Paste {
+    pub buffer_index: usize,
+    // the length of the buffer's history right after the paste
+    pub transactions: usize,
+    pub x: i32,
+    pub y: i32,
+    pub before: bool,
+    pub ring_index: usize,
+}
+
+// "" is the unnamed register, "a to "z the named ones, "0 the last yank,
+// "1 to "9 the last deletes of a line or more, "- the last smaller delete,
+// "+ and "* the system clipboard and "_ a black hole.
+pub struct Registers {
+    pub unnamed: Option<Clip>,
+    pub named: HashMap<char, Clip>,
+    pub yank: Option<Clip>,
+    pub deletes: Vec<Clip>,
+    pub small_delete: Option<Clip>,
+    // every yank and delete, newest last
+    pub kill_ring: Vec<Clip>,
+    pub clipboard: Clipboard,
+}
+
+pub fn is_register(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || "\"-+*_".contains(ch)
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            unnamed: None,
+            named: HashMap::new(),
+            yank: None,
+            deletes: vec![],
+            small_delete: None,
+            kill_ring: vec![],
+            clipboard: Clipboard::new("internal", "", ""),
+        }
+    }
+
+    // Stores a yank or a delete in `register`, or where vim would put it
+    // without one. An uppercase register appends to the lowercase one. The
+    // clip is kept even when the clipboard can't take it.
+    pub fn store(&mut self, register: Option<char>, mut clip: Clip, yank: bool) -> Result<(), String> {
+        let mut copied = Ok(());
+        match register {
+            Some('_') => { return Ok(()); },
+            Some(ch) if ch.is_ascii_alphabetic() => {
+                let name = ch.to_ascii_lowercase();
+                if let Some(old) = self.named.get(&name).filter(|_| ch != name) {
+                    // appending lines to text makes it lines too
+                    let kind = if clip.kind == "linewise" { "linewise" } else { old.kind };
+                    let joiner = if kind != "charwise" && !old.text.ends_with("\n") { "\n" } else { "" };
+                    clip = Clip { text: format!("{}{}{}", old.text, joiner, clip.text), kind: kind };
+                }
+                self.named.insert(name, clip.clone());
+                self.unnamed = Some(clip.clone());
+            },
+            Some('+') | Some('*') => {
+                copied = self.clipboard.copy(&clip.text);
+                self.unnamed = Some(clip.clone());
+            },
+            _ => {
+                if yank {
+                    self.yank = Some(clip.clone());
+                } else if clip.kind == "linewise" || clip.text.contains('\n') {
+                    self.deletes.insert(0, clip.clone());
+                    self.deletes.truncate(9);
+                } else {
+                    self.small_delete = Some(clip.clone());
+                }
+                self.unnamed = Some(clip.clone());
+            }
+        }
+        self.kill_ring.push(clip);
+        if self.kill_ring.len() > KILL_RING_SIZE {
+            self.kill_ring.remove(0);
+        }
+        copied
+    }
+
+    pub fn get(&self, register: Option<char>) -> Result<Option<Clip>, String> {
+        Ok(match register {
+            None | Some('"') => self.unnamed.clone(),
+            Some('0') => self.yank.clone(),
+            Some(ch) if ch.is_digit(10) => self.deletes.get(ch.to_digit(10).unwrap() as usize - 1).cloned(),
+            Some('-') => self.small_delete.clone(),
+            Some('+') | Some('*') => {
+                let text = self.clipboard.paste()?;
+                let kind = if text.ends_with("\n") { "linewise" } else { "charwise" };
+                Some(Clip { text: text, kind: kind })
+            },
+            Some(ch) if ch.is_ascii_alphabetic() => self.named.get(&ch.to_ascii_lowercase()).cloned(),
+            _ => None,
+        })
+    }
+}
+
+// How a register's contents are shown in a single line.
+fn preview(text: &str) -> String {
+    text.replace('\n', "^J").replace('\t', "^I")
+}
+
+impl Editor {
+    // The register typed after ", for the next yank, delete or paste.
+    pub fn handle_select_register(&mut self, key: &str) {
+        let window = self.window_tree.find_active_window().unwrap();
+        window.mode = window.register_mode.take().unwrap_or("normal".to_string());
+        let mut chars = key.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) if is_register(ch) => { window.register = Some(ch); },
+            _ => { window.count = None; }
+        }
+    }
+
+    // Saves the text `region` covers before an operator changes it.
+    pub fn store_region(&mut self, region: Region, yank: bool) {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        let clip = if region.linewise {
+            let end_y = region.end.1;
+            let end = (buffer.lines[end_y as usize].len() as i32, end_y);
+            Clip { text: buffer.text_between((0, region.start.1), end) + "\n", kind: "linewise" }
+        } else {
+            Clip { text: buffer.text_between(region.start, region.end), kind: "charwise" }
+        };
+        if let Err(error) = self.registers.store(window.register.take(), clip, yank) {
+            self.message = Some(error);
+        }
+    }
+
+    // p and P: `count` copies of a register's text after or before the
+    // cursor, or below or above its line for whole lines.
+    pub fn paste(&mut self, before: bool) {
+        let (register, count) = {
+            let window = self.window_tree.find_active_window().unwrap();
+            (window.register.take(), window.take_count())
+        };
+        let clip = match self.registers.get(register) {
+            Ok(Some(clip)) => clip,
+            Err(error) => {
+                self.message = Some(error);
+                return;
+            },
+            Ok(None) => {
+                self.message = Some(match register {
+                    Some(ch) => format!("Nothing in register {}", ch),
+                    None => "Nothing to paste".to_string(),
+                });
+                return;
+            }
+        };
+        // yank-pop starts from the kill before this one
+        let ring = &self.registers.kill_ring;
+        let ring_index = match ring.last() {
+            Some(last) if *last == clip => ring.len() - 1,
+            _ => ring.len(),
+        };
+        self.put(&clip, count, before, ring_index);
+    }
+
+    // Inserts the clip as one undo step and leaves the cursor where vim
+    // does: on the last character of a short paste, at the start of a
+    // longer one, and on the first non-blank of pasted lines.
+    fn put(&mut self, clip: &Clip, count: i32, before: bool, ring_index: usize) {
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let index = window.buffer_index as usize;
+        let ref mut buffer = self.buffers[index];
+        let (x, y) = (window.cursor_x, window.cursor_y);
+        let count = max(1, count) as usize;
+        let after_x = if buffer.lines[y as usize].len() == 0 { 0 } else { x + 1 };
+        buffer.begin_group();
+        match clip.kind {
+            "linewise" => {
+                let body = clip.text.trim_end_matches('\n');
+                let body = vec![body; count].join("\n");
+                let line = if before {
+                    buffer.insert(&(body + "\n"), 0, y, true);
+                    y
+                } else {
+                    let length = buffer.lines[y as usize].len() as i32;
+                    buffer.insert(&("\n".to_string() + &body), length, y, true);
+                    y + 1
+                };
+                window.cursor_y = line;
+                window.cursor_x = buffer.first_non_blank(line);
+            },
+            "blockwise" => {
+                let column = buffer.display_col(if before { x } else { after_x }, y);
+                for (i, row) in clip.text.split('\n').enumerate() {
+                    let line = y + i as i32;
+                    if line >= buffer.eof() {
+                        let length = buffer.lines[(line - 1) as usize].len() as i32;
+                        buffer.insert("\n", length, line - 1, true);
+                    }
+                    // short lines are padded out to the block's column
+                    buffer.pad_to_col(column, line);
+                    let at = buffer.x_at_col(column, line);
+                    buffer.insert(&vec![row; count].concat(), at, line, true);
+                }
+                window.cursor_y = y;
+                window.cursor_x = if before { x } else { after_x };
+            },
+            _ => {
+                let text = vec![clip.text.as_str(); count].concat();
+                let at = if before { x } else { after_x };
+                buffer.insert(&text, at, y, true);
+                window.cursor_y = y;
+                window.cursor_x = if text.contains('\n') {
+                    at
+                } else {
+                    at + max(1, text.chars().count() as i32) - 1
+                };
+            }
+        }
+        buffer.end_group();
+        window.cursor_x = min(window.cursor_x, buffer.eol(window.cursor_y));
+        window.col = window.cursor_x;
+        window.row = window.cursor_y;
+        window.scroll_to_cursor(window_height);
+        self.last_paste = Some(Paste {
+            buffer_index: index,
+            transactions: buffer.transactions.len(),
+            x: x,
+            y: y,
+            before: before,
+            ring_index: ring_index,
+        });
+    }
+
+    // Right after a paste, replaces what was pasted with the kill before
+    // it, going round the kill ring.
+    pub fn yank_pop(&mut self) {
+        let paste = match self.last_paste.take() {
+            Some(paste) => paste,
+            None => {
+                self.message = Some("Previous command was not a paste".to_string());
+                return;
+            }
+        };
+        let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        let ring_len = self.registers.kill_ring.len();
+        if index != paste.buffer_index || self.buffers[index].transactions.len() != paste.transactions || ring_len == 0 {
+            self.message = Some("Previous command was not a paste".to_string());
+            return;
+        }
+        self.buffers[index].undo();
+        let ring_index = (paste.ring_index + ring_len - 1) % ring_len;
+        let clip = self.registers.kill_ring[ring_index].clone();
+        {
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref buffer = self.buffers[index];
+            window.cursor_y = min(paste.y, buffer.eof() - 1);
+            window.cursor_x = paste.x;
+        }
+        self.put(&clip, 1, paste.before, ring_index);
+    }
+
+    // Shows what's in each register, and the kill ring.
+    pub fn list_registers(&mut self) {
+        let mut lines = vec!["Register  Contents".to_string()];
+        let mut show = |name: char, clip: Option<&Clip>| {
+            if let Some(clip) = clip {
+                lines.push(format!("\"{}        {}", name, preview(&clip.text)));
+            }
+        };
+        show('"', self.registers.unnamed.as_ref());
+        show('0', self.registers.yank.as_ref());
+        for (i, clip) in self.registers.deletes.iter().enumerate() {
+            show(char::from_digit(i as u32 + 1, 10).unwrap(), Some(clip));
+        }
+        show('-', self.registers.small_delete.as_ref());
+        let mut names: Vec<&char> = self.registers.named.keys().collect();
+        names.sort();
+        for name in names {
+            show(*name, self.registers.named.get(name));
+        }
+        lines.push(format!("\"+ and \"* use the {} clipboard", self.registers.clipboard.provider));
+        lines.push(String::new());
+        lines.push("Kill ring, newest first".to_string());
+        for clip in self.registers.kill_ring.iter().rev() {
+            lines.push(format!("          {}", preview(&clip.text)));
+        }
+        self.split_towards(SOUTH);
+        self.open_scratch("*registers*", lines);
+    }
+}
@@ -0,0 +1,85 @@
+use std::mem;
+
+use editor::Editor;
+
+impl Editor {
+    // Between commands in normal mode, with nothing half typed.
+    fn between_commands(&mut self) -> bool {
+        let pending = self.pending_keys.len() > 0;
+        let window = self.window_tree.find_active_window().unwrap();
+        window.mode == "normal" && !pending && window.count.is_none() &&
+            window.register.is_none() && window.operator.is_none()
+    }
+
+    // Collects the keys of the command being typed, from the first key
+    // typed in normal mode until it's back in normal mode, so a command
+    // that changes the buffer can be repeated with `.`.
+    pub fn start_change_key(&mut self, key: &str) {
+        if self.repeating {
+            return;
+        }
+        if self.between_commands() {
+            let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+            self.change_keys.clear();
+            self.change_start = Some((index, self.buffers[index].group));
+        }
+        self.change_keys.push((key.to_string(), false));
+    }
+
+    // The key just collected went into a count rather than the command.
+    pub fn count_change_key(&mut self) {
+        if self.repeating {
+            return;
+        }
+        if let Some(key) = self.change_keys.last_mut() {
+            key.1 = true;
+        }
+    }
+
+    pub fn end_change_key(&mut self) {
+        if self.repeating || !self.between_commands() {
+            return;
+        }
+        if let Some((index, group)) = self.change_start.take() {
+            // anything recorded since is a change; undoing only removes
+            let changed = self.buffers.get(index)
+                .and_then(|buffer| buffer.transactions.last())
+                .map(|t| t.group > group)
+                .unwrap_or(false);
+            if changed {
+                self.last_change = mem::replace(&mut self.change_keys, vec![]);
+            }
+        }
+        self.change_keys.clear();
+    }
+
+    // `.` types the last change again, as one undo step. A count replaces
+    // the counts it was typed with, for this and later repeats, so after
+    // `2d3w`, `5.` deletes five words.
+    pub fn repeat_change(&mut self) {
+        let (count, index) = {
+            let window = self.window_tree.find_active_window().unwrap();
+            (window.count.take(), window.buffer_index as usize)
+        };
+        // `.` itself isn't the change to repeat
+        self.change_start = None;
+        if self.last_change.len() == 0 {
+            return;
+        }
+        let mut keys = self.last_change.clone();
+        if let Some(count) = count {
+            keys = count.to_string().chars().map(|c| (c.to_string(), true))
+                .chain(keys.into_iter().filter(|&(_, counted)| !counted))
+                .collect();
+            self.last_change = keys.clone();
+        }
+        self.repeating = true;
+        self.buffers[index].begin_group();
+        for &(ref key, _) in &keys {
+            self.process_key(key);
+        }
+        self.flush_pending_keys();
+        self.buffers[index].end_group();
+        self.repeating = false;
+    }
+}
@@ -0,0 +1,277 @@
+use regex;
+use regex::Regex;
+
+use buffer::Buffer;
+use drawer::Drawer;
+use editor::Editor;
+use motion;
+use motion::Target;
+use util;
+
+// The last search, which n and N repeat and whose matches stay highlighted.
+#[derive(Clone)]
+pub struct Search {
+    pub pattern: String,
+    pub forward: bool,
+}
+
+// Where the cursor was when the search prompt opened, to go back to if the
+// search is abandoned.
+pub struct SearchOrigin {
+    pub forward: bool,
+    pub x: i32,
+    pub y: i32,
+    pub scroll_y: i32,
+    pub mode: String,
+}
+
+// Smart case: a pattern without capitals matches either case. Escapes like
+// `\S` and `\W` aren't capitals.
+pub fn compile(pattern: &str) -> Option<Regex> {
+    if pattern.len() == 0 {
+        return None;
+    }
+    let mut escaped = false;
+    let capitals = pattern.chars().any(|c| {
+        let capital = !escaped && c.is_uppercase();
+        escaped = !escaped && c == '\\';
+        capital
+    });
+    if capitals {
+        Regex::new(pattern).ok()
+    } else {
+        Regex::new(&format!("(?i){}", pattern)).ok()
+    }
+}
+
+// The matches of `re` in `text`, as character ranges.
+pub fn match_ranges(re: &Regex, text: &str) -> Vec<(usize, usize)> {
+    re.find_iter(text).map(|(start, end)| {
+        (text[..start].chars().count(), text[..end].chars().count())
+    }).collect()
+}
+
+// The word at (x, y) and where it starts.
+pub fn word_under(buffer: &Buffer, x: i32, y: i32) -> Option<(i32, String)> {
+    let ref line = buffer.lines[y as usize];
+    let x = x as usize;
+    if !line.get(x).map(|c| motion::is_word_char(buffer, c.ch)).unwrap_or(false) {
+        return None;
+    }
+    let start = line[..x].iter().rposition(|c| !motion::is_word_char(buffer, c.ch)).map(|i| i + 1).unwrap_or(0);
+    let end = line[x..].iter().position(|c| !motion::is_word_char(buffer, c.ch)).map(|i| x + i).unwrap_or(line.len());
+    Some((start as i32, line[start..end].iter().map(|c| c.ch).collect()))
+}
+
+// The start of the next match after (x, y), or the previous one before it,
+// wrapping around the buffer. Also says whether it wrapped.
+pub fn find_match(buffer: &Buffer, re: &Regex, x: i32, y: i32, forward: bool) -> Option<((i32, i32), bool)> {
+    let eof = buffer.eof();
+    let starts = |y: i32| -> Vec<i32> {
+        match_ranges(re, &buffer.line_text(y)).iter().map(|&(start, _)| start as i32).collect()
+    };
+    let here = starts(y);
+    if forward {
+        if let Some(&start) = here.iter().find(|&&start| start > x) {
+            return Some(((start, y), false));
+        }
+        for i in 1..(eof + 1) {
+            let line = (y + i) % eof;
+            let found = if line == y {
+                here.first().cloned()
+            } else {
+                starts(line).first().cloned()
+            };
+            if let Some(start) = found {
+                return Some(((start, line), y + i >= eof));
+            }
+        }
+    } else {
+        if let Some(&start) = here.iter().rev().find(|&&start| start < x) {
+            return Some(((start, y), false));
+        }
+        for i in 1..(eof + 1) {
+            let line = (y - i + eof) % eof;
+            let found = if line == y {
+                here.last().cloned()
+            } else {
+                starts(line).last().cloned()
+            };
+            if let Some(start) = found {
+                return Some(((start, line), y - i < 0));
+            }
+        }
+    }
+    None
+}
+
+impl Editor {
+    pub fn start_search(&mut self, forward: bool) {
+        let window = self.window_tree.find_active_window().unwrap();
+        self.search_origin = Some(SearchOrigin {
+            forward: forward,
+            x: window.cursor_x,
+            y: window.cursor_y,
+            scroll_y: window.scroll_y,
+            mode: window.mode.clone(),
+        });
+        window.mode = "search".to_string();
+        self.drawer = Some(Drawer::new_prompt("search", if forward { "/" } else { "?" }));
+    }
+
+    pub fn handle_search(&mut self, key: &str) {
+        match key {
+            "<Escape>" | "<C-g>" => {
+                self.end_search();
+                let window = self.window_tree.find_active_window().unwrap();
+                if window.mode == "operator" {
+                    window.cancel_operator();
+                }
+            },
+            "<Enter>" => {
+                let value = self.drawer.as_ref().unwrap().value.clone();
+                let forward = self.search_origin.as_ref().unwrap().forward;
+                // an empty pattern repeats the last one
+                let pattern = if value.len() == 0 {
+                    match self.search {
+                        Some(ref search) => search.pattern.clone(),
+                        None => { self.end_search(); return; }
+                    }
+                } else {
+                    value
+                };
+                if compile(&pattern).is_none() {
+                    self.message = Some(format!("Invalid pattern: {}", pattern));
+                    self.end_search();
+                    return;
+                }
+                self.search = Some(Search { pattern: pattern, forward: forward });
+                self.search_highlight = true;
+                self.end_search();
+                if self.window_tree.find_active_window().unwrap().operator.is_none() {
+                    self.push_jump();
+                }
+                if let Some(target) = self.motion_target("search-next", None) {
+                    self.finish_motion(target);
+                }
+            },
+            "<Backspace>" | "<DEL>" if self.drawer.as_ref().unwrap().value.len() == 0 => { self.end_search(); },
+            _ => {
+                self.drawer.as_mut().unwrap().handle_key(key);
+                self.jump_to_match();
+            }
+        }
+    }
+
+    // Moves to the first match of what's been typed so far, or back to
+    // where the search started if there isn't one.
+    fn jump_to_match(&mut self) {
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        let origin = self.search_origin.as_ref().unwrap();
+        window.cursor_x = origin.x;
+        window.cursor_y = origin.y;
+        window.scroll_y = origin.scroll_y;
+        let found = compile(&self.drawer.as_ref().unwrap().value)
+            .and_then(|re| find_match(buffer, &re, origin.x, origin.y, origin.forward));
+        if let Some(((x, y), _)) = found {
+            window.cursor_x = x;
+            window.cursor_y = y;
+            window.scroll_to_cursor(window_height);
+        }
+    }
+
+    // Closes the prompt and puts the cursor back where it started.
+    fn end_search(&mut self) {
+        let window = self.window_tree.find_active_window().unwrap();
+        if let Some(origin) = self.search_origin.take() {
+            window.cursor_x = origin.x;
+            window.cursor_y = origin.y;
+            window.scroll_y = origin.scroll_y;
+            window.mode = origin.mode;
+        }
+        window.col = window.cursor_x;
+        window.row = window.cursor_y;
+        self.drawer = None;
+    }
+
+    // The target of n, N, * and #. `count` matches on, with a note on the
+    // message line when the search wraps or finds nothing.
+    pub fn search_target(&mut self, name: &str, count: Option<i32>) -> Option<Target> {
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref buffer = self.buffers[window.buffer_index as usize];
+        let search = match name {
+            "search-word-forward" | "search-word-backward" => {
+                let word = match word_under(buffer, window.cursor_x, window.cursor_y) {
+                    Some((_, word)) => word,
+                    None => {
+                        self.message = Some("No word under the cursor".to_string());
+                        return None;
+                    }
+                };
+                let search = Search {
+                    pattern: format!(r"\b{}\b", regex::quote(&word)),
+                    forward: name == "search-word-forward",
+                };
+                self.search = Some(search.clone());
+                self.search_highlight = true;
+                search
+            },
+            _ => {
+                match self.search {
+                    Some(ref search) => Search {
+                        pattern: search.pattern.clone(),
+                        forward: search.forward == (name == "search-next"),
+                    },
+                    None => {
+                        self.message = Some("No previous search".to_string());
+                        return None;
+                    }
+                }
+            }
+        };
+        let re = match compile(&search.pattern) {
+            Some(re) => re,
+            None => { return None; }
+        };
+
+        let mut pos = (window.cursor_x, window.cursor_y);
+        let mut wrapped = false;
+        for _ in 0..count.unwrap_or(1) {
+            match find_match(buffer, &re, pos.0, pos.1, search.forward) {
+                Some((p, w)) => {
+                    pos = p;
+                    wrapped = wrapped || w;
+                },
+                None => {
+                    self.message = Some(format!("Pattern not found: {}", search.pattern));
+                    return None;
+                }
+            }
+        }
+        self.search_highlight = true;
+        if wrapped {
+            self.message = Some(if search.forward {
+                "search hit BOTTOM, continuing at TOP".to_string()
+            } else {
+                "search hit TOP, continuing at BOTTOM".to_string()
+            });
+        }
+        Some(Target { x: pos.0, y: pos.1, linewise: false, inclusive: false })
+    }
+
+    // The pattern to highlight: what's being typed, or the last search.
+    pub fn highlight_pattern(&self) -> Option<Regex> {
+        if self.search_origin.is_some() {
+            self.drawer.as_ref().and_then(|d| compile(&d.value))
+        } else if let Some(re) = self.command_line_pattern() {
+            Some(re)
+        } else if self.search_highlight {
+            self.search.as_ref().and_then(|s| compile(&s.pattern))
+        } else {
+            None
+        }
+    }
+}
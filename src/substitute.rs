@@ -0,0 +1,341 @@
+use std::cmp::{min, max};
+
+use regex::{Captures, Regex};
+
+use buffer::Buffer;
+use editor::Editor;
+use ex::Range;
+use search;
+use search::Search;
+use util;
+
+// `/pattern/replacement/flags` as typed after :s. The replacement is None
+// until the delimiter after the pattern has been typed.
+pub struct Parsed {
+    pub pattern: String,
+    pub replacement: Option<String>,
+    pub flags: String,
+}
+
+impl Parsed {
+    // `i` ignores case and `I` doesn't; otherwise it's smart case like a search.
+    pub fn regex(&self) -> Option<Regex> {
+        if self.pattern.len() == 0 {
+            None
+        } else if self.flags.contains('i') {
+            Regex::new(&format!("(?i){}", self.pattern)).ok()
+        } else if self.flags.contains('I') {
+            Regex::new(&self.pattern).ok()
+        } else {
+            search::compile(&self.pattern)
+        }
+    }
+}
+
+// Any punctuation can be the delimiter, and a backslash escapes it.
+pub fn parse(text: &str) -> Option<Parsed> {
+    let delimiter = match text.chars().next() {
+        Some(c) if !c.is_alphanumeric() && !c.is_whitespace() && c != '\\' && c != '"' => c,
+        _ => { return None; }
+    };
+    let mut pieces = vec![String::new()];
+    let mut chars = text[delimiter.len_utf8()..].chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(next) if next == delimiter => { pieces.last_mut().unwrap().push(next); },
+                Some(next) => {
+                    pieces.last_mut().unwrap().push(c);
+                    pieces.last_mut().unwrap().push(next);
+                },
+                None => { pieces.last_mut().unwrap().push(c); }
+            }
+        } else if c == delimiter && pieces.len() < 3 {
+            pieces.push(String::new());
+        } else {
+            pieces.last_mut().unwrap().push(c);
+        }
+    }
+    let mut pieces = pieces.into_iter();
+    Some(Parsed {
+        pattern: pieces.next().unwrap(),
+        replacement: pieces.next(),
+        flags: pieces.next().unwrap_or(String::new()),
+    })
+}
+
+// The replacement for one match: `&` or `\0` is the whole match, `\1` to
+// `\9` its groups, and `\n` or `\r` a line break.
+fn expand(caps: &Captures, replacement: &str) -> String {
+    let mut result = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => { result.push_str(caps.at(0).unwrap_or("")); },
+            '\\' => {
+                match chars.next() {
+                    Some(d) if d.is_digit(10) => {
+                        result.push_str(caps.at(d.to_digit(10).unwrap() as usize).unwrap_or(""));
+                    },
+                    Some('n') | Some('r') => { result.push('\n'); },
+                    Some('t') => { result.push('\t'); },
+                    Some(other) => { result.push(other); },
+                    None => { result.push('\\'); }
+                }
+            },
+            _ => { result.push(c); }
+        }
+    }
+    result
+}
+
+// A substitution in progress over a range of lines. With the `c` flag it
+// waits on each match for y, n, a, l or q.
+pub struct Substitution {
+    pattern: String,
+    re: Regex,
+    replacement: String,
+    global: bool,
+    // where to look for the next match, and the last line of the range,
+    // which moves down as replacements add lines
+    x: i32,
+    y: i32,
+    end: i32,
+    // the match being asked about: line, columns and replacement
+    current: Option<(i32, i32, i32, String)>,
+    found: bool,
+    count: usize,
+    lines: usize,
+    last_line: Option<i32>,
+}
+
+impl Substitution {
+    fn next_match(&self, buffer: &Buffer) -> Option<(i32, i32, i32, String)> {
+        let mut x = self.x;
+        let mut y = self.y;
+        while y <= self.end {
+            let text = buffer.line_text(y);
+            for caps in self.re.captures_iter(&text) {
+                let (start, end) = caps.pos(0).unwrap();
+                let start_x = text[..start].chars().count() as i32;
+                if start_x >= x {
+                    let end_x = text[..end].chars().count() as i32;
+                    return Some((y, start_x, end_x, expand(&caps, &self.replacement)));
+                }
+            }
+            x = 0;
+            y += 1;
+        }
+        None
+    }
+
+    // Replaces the match, or skips it, and moves on past it.
+    fn step(&mut self, buffer: &mut Buffer, found: (i32, i32, i32, String), replace: bool) {
+        let (y, start, end, text) = found;
+        self.found = true;
+        let mut added = 0;
+        let mut after = end;
+        if replace {
+            buffer.remove_text(start, y, (end - start) as usize, true);
+            if text.len() > 0 {
+                buffer.insert(&text, start, y, true);
+            }
+            added = text.matches('\n').count() as i32;
+            after = match text.rfind('\n') {
+                Some(i) => text[(i + 1)..].chars().count() as i32,
+                None => start + text.chars().count() as i32,
+            };
+            self.count += 1;
+            if self.last_line != Some(y + added) {
+                self.lines += 1;
+            }
+            self.last_line = Some(y + added);
+        }
+        // an empty match can't match again in the same place
+        if start == end {
+            after += 1;
+        }
+        self.end += added;
+        if self.global {
+            self.x = after;
+            self.y = y + added;
+        } else {
+            self.x = 0;
+            self.y = y + added + 1;
+        }
+    }
+
+    // Replaces every match left without asking.
+    fn run(&mut self, buffer: &mut Buffer) {
+        while let Some(found) = self.next_match(buffer) {
+            self.step(buffer, found, true);
+        }
+    }
+}
+
+impl Editor {
+    fn substitution(&self, range: Range, args: &str) -> Result<(Substitution, Parsed), String> {
+        let mut parsed = match parse(args.trim_start()) {
+            Some(parsed) => parsed,
+            None => { return Err("Usage: s/pattern/replacement/flags".to_string()); }
+        };
+        if let Some(c) = parsed.flags.chars().find(|c| !"gciI".contains(*c)) {
+            return Err(format!("Unknown flag: {}", c));
+        }
+        // an empty pattern is the last search
+        if parsed.pattern.len() == 0 {
+            match self.search {
+                Some(ref search) => { parsed.pattern = search.pattern.clone(); },
+                None => { return Err("No previous regular expression".to_string()); }
+            }
+        }
+        let re = match parsed.regex() {
+            Some(re) => re,
+            None => { return Err(format!("Invalid pattern: {}", parsed.pattern)); }
+        };
+        let substitution = Substitution {
+            pattern: parsed.pattern.clone(),
+            re: re,
+            replacement: parsed.replacement.clone().unwrap_or(String::new()),
+            global: parsed.flags.contains('g'),
+            x: 0,
+            y: range.start,
+            end: range.end,
+            current: None,
+            found: false,
+            count: 0,
+            lines: 0,
+            last_line: None,
+        };
+        Ok((substitution, parsed))
+    }
+
+    // :s over `range`. The whole substitution is undone in one go, even when
+    // it asks about each match.
+    pub fn substitute(&mut self, range: Range, args: &str) {
+        let (mut substitution, parsed) = match self.substitution(range, args) {
+            Ok(result) => result,
+            Err(error) => { self.message = Some(error); return; }
+        };
+        self.search = Some(Search { pattern: parsed.pattern.clone(), forward: true });
+        let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        self.buffers[index].begin_group();
+        if parsed.flags.contains('c') {
+            self.substitution = Some(substitution);
+            self.window_tree.find_active_window().unwrap().mode = "substitute_confirm".to_string();
+            self.confirm_next();
+        } else {
+            substitution.run(&mut self.buffers[index]);
+            self.finish_substitution(substitution);
+        }
+    }
+
+    // Applies a substitution being typed, undoably, without moving the
+    // cursor. Returns whether it changed anything.
+    pub fn preview_substitute(&mut self, range: Range, args: &str) -> bool {
+        let (mut substitution, parsed) = match self.substitution(range, args) {
+            Ok(result) => result,
+            Err(_) => { return false; }
+        };
+        if parsed.replacement.is_none() {
+            return false;
+        }
+        let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        let ref mut buffer = self.buffers[index];
+        buffer.begin_group();
+        substitution.run(buffer);
+        buffer.end_group();
+        substitution.count > 0
+    }
+
+    pub fn handle_substitute_confirm(&mut self, key: &str) {
+        let mut substitution = self.substitution.take().unwrap();
+        let found = substitution.current.take().unwrap();
+        let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+        match key {
+            "y" => {
+                substitution.step(&mut self.buffers[index], found, true);
+                self.substitution = Some(substitution);
+                self.confirm_next();
+            },
+            "n" => {
+                substitution.step(&mut self.buffers[index], found, false);
+                self.substitution = Some(substitution);
+                self.confirm_next();
+            },
+            "a" => {
+                substitution.step(&mut self.buffers[index], found, true);
+                substitution.run(&mut self.buffers[index]);
+                self.finish_substitution(substitution);
+            },
+            "l" => {
+                substitution.step(&mut self.buffers[index], found, true);
+                self.finish_substitution(substitution);
+            },
+            "q" | "<Escape>" | "<C-g>" => { self.finish_substitution(substitution); },
+            _ => {
+                substitution.current = Some(found);
+                self.substitution = Some(substitution);
+                self.show_confirm();
+            }
+        }
+    }
+
+    // Selects the next match and asks about it, or finishes if there are no
+    // more.
+    fn confirm_next(&mut self) {
+        let mut substitution = self.substitution.take().unwrap();
+        let found = {
+            let index = self.window_tree.find_active_window().unwrap().buffer_index as usize;
+            substitution.next_match(&self.buffers[index])
+        };
+        match found {
+            Some(found) => {
+                let (max_x, max_y) = util::window_area();
+                let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+                let window = self.window_tree.find_active_window().unwrap();
+                let (y, start, end, _) = found.clone();
+                window.mark = Some((y, start));
+                window.cursor_y = y;
+                window.cursor_x = max(start, end - 1);
+                window.scroll_to_cursor(window_height);
+                substitution.current = Some(found);
+                self.substitution = Some(substitution);
+                self.show_confirm();
+            },
+            None => { self.finish_substitution(substitution); }
+        }
+    }
+
+    fn show_confirm(&mut self) {
+        if let Some((_, _, _, ref text)) = self.substitution.as_ref().unwrap().current {
+            self.message = Some(format!("replace with {} (y/n/a/q/l)?", text));
+        }
+    }
+
+    // Closes the undo group and puts the cursor on the last line changed.
+    fn finish_substitution(&mut self, substitution: Substitution) {
+        let (max_x, max_y) = util::window_area();
+        let window_height = self.window_tree.find_active_window_height(max_x, max_y, 0, 0);
+        let window = self.window_tree.find_active_window().unwrap();
+        let ref mut buffer = self.buffers[window.buffer_index as usize];
+        buffer.end_group();
+        window.mark = None;
+        window.mode = "normal".to_string();
+        if let Some(y) = substitution.last_line {
+            window.cursor_y = y;
+            window.cursor_x = buffer.first_non_blank(y);
+            window.scroll_to_cursor(window_height);
+        } else {
+            window.cursor_x = min(window.cursor_x, buffer.eol(window.cursor_y));
+        }
+        window.col = window.cursor_x;
+        window.row = window.cursor_y;
+        self.message = match (substitution.count, substitution.lines) {
+            (0, _) if !substitution.found => Some(format!("Pattern not found: {}", substitution.pattern)),
+            (0, _) | (1, _) => None,
+            (n, 1) => Some(format!("{} substitutions on 1 line", n)),
+            (n, lines) => Some(format!("{} substitutions on {} lines", n, lines)),
+        };
+    }
+}
@@ -0,0 +1,108 @@
+use syntect::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp};
+
+use buffer::Buffer;
+
+// Parses the buffer with its syntax and calls `f` with the scopes of every
+// character, line by line. None if the buffer has no syntax.
+pub fn scope_map<T, F>(buffer: &Buffer, f: F) -> Option<Vec<Vec<T>>> where F: Fn(&ScopeStack) -> T {
+    let mut result = vec![];
+    for y in 0..buffer.lines.len() {
+        let text = buffer.line_text(y as i32);
+        let (mut stack, ops) = match parse_line(buffer, y as i32) {
+            Some(parsed) => parsed,
+            None => { return None; }
+        };
+        // the offsets are in bytes
+        let mut i = 0;
+        let mut values = vec![];
+        for (offset, _) in text.char_indices() {
+            while i < ops.len() && ops[i].0 <= offset {
+                stack.apply(&ops[i].1);
+                i += 1;
+            }
+            values.push(f(&stack));
+        }
+        result.push(values);
+    }
+    Some(result)
+}
+
+pub fn in_scope(stack: &ScopeStack, scope: Scope) -> bool {
+    stack.as_slice().iter().any(|s| scope.is_prefix_of(*s))
+}
+
+// The parser's state at the start of line y, carrying on from the last
+// line the buffer has kept one for, so only lines edited since are parsed
+// again. None if the buffer has no syntax.
+fn state_at(buffer: &Buffer, y: i32) -> Option<(ParseState, ScopeStack)> {
+    if buffer.parse_states.borrow().len() == 0 {
+        let state = match buffer.highlighter {
+            Some(ref syntax) => ParseState::new(syntax),
+            None => { return None; }
+        };
+        buffer.parse_states.borrow_mut().push((state, ScopeStack::new()));
+    }
+    loop {
+        let line = buffer.parse_states.borrow().len() - 1;
+        if line >= y as usize {
+            break;
+        }
+        parse_line(buffer, line as i32);
+    }
+    Some(buffer.parse_states.borrow()[y as usize].clone())
+}
+
+// The scopes at the start of line y and the line's changes to them,
+// keeping the state the line ends in for the next one.
+fn parse_line(buffer: &Buffer, y: i32) -> Option<(ScopeStack, Vec<(usize, ScopeStackOp)>)> {
+    let (mut state, stack) = match state_at(buffer, y) {
+        Some(state) => state,
+        None => { return None; }
+    };
+    let ops = state.parse_line(&buffer.line_text(y));
+    let mut states = buffer.parse_states.borrow_mut();
+    if states.len() == y as usize + 1 {
+        let mut next = stack.clone();
+        for &(_, ref op) in &ops {
+            next.apply(op);
+        }
+        states.push((state, next));
+    }
+    Some((stack, ops))
+}
+
+// Whether x on line y, which can be the end of the line, is inside a string
+// or just after something in a comment.
+pub fn in_string_or_comment(buffer: &Buffer, x: i32, y: i32) -> bool {
+    let (mut stack, ops) = match parse_line(buffer, y) {
+        Some(parsed) => parsed,
+        None => { return false; }
+    };
+    let string = Scope::new("string").unwrap();
+    let comment = Scope::new("comment").unwrap();
+    let text = buffer.line_text(y);
+    let offset = |x: i32| text.char_indices().nth(x as usize).map(|(i, _)| i).unwrap_or(text.len());
+    let mut i = 0;
+    let mut commented = false;
+    // a string's closing quote is popped after it, a line comment at the
+    // end of the line, so strings are looked for at x and comments before it
+    if x > 0 {
+        while i < ops.len() && ops[i].0 <= offset(x - 1) {
+            stack.apply(&ops[i].1);
+            i += 1;
+        }
+        commented = in_scope(&stack, comment);
+    }
+    while i < ops.len() && ops[i].0 <= offset(x) {
+        stack.apply(&ops[i].1);
+        i += 1;
+    }
+    commented || in_scope(&stack, string)
+}
+
+// Which characters are inside a string or a comment.
+pub fn strings_and_comments(buffer: &Buffer) -> Option<Vec<Vec<bool>>> {
+    let string = Scope::new("string").unwrap();
+    let comment = Scope::new("comment").unwrap();
+    scope_map(buffer, |stack| in_scope(stack, string) || in_scope(stack, comment))
+}
@@ -0,0 +1,398 @@
+use std::cmp::{min};
+use regex::Regex;
+use syntect::parsing::Scope;
+
+use buffer::Buffer;
+use editor::Editor;
+use motion;
+use motion::BLANK;
+use operator::Region;
+use syntax;
+
+// "inner-" objects leave out the delimiters and surrounding blanks that the
+// "around-" ones take in.
+pub static TEXT_OBJECTS: &'static [&'static str] = &[
+    "around-angle",
+    "around-brace",
+    "around-bracket",
+    "around-double-quote",
+    "around-function",
+    "around-paragraph",
+    "around-paren",
+    "around-single-quote",
+    "around-tag",
+    "around-word",
+    "inner-angle",
+    "inner-brace",
+    "inner-bracket",
+    "inner-double-quote",
+    "inner-function",
+    "inner-paragraph",
+    "inner-paren",
+    "inner-single-quote",
+    "inner-tag",
+    "inner-word",
+];
+
+lazy_static! {
+    // opening, closing and self-closing tags: </ name ... />
+    static ref TAG: Regex = Regex::new(r"<(/?)([A-Za-z][\w:.-]*)[^>]*?(/?)>").unwrap();
+}
+
+pub fn is_text_object(name: &str) -> bool {
+    TEXT_OBJECTS.contains(&name)
+}
+
+// The region text object `name` covers at (x, y). For brackets and tags,
+// `count` picks how many levels out to go.
+pub fn find(buffer: &Buffer, name: &str, x: i32, y: i32, count: i32) -> Option<Region> {
+    let around = name.starts_with("around-");
+    let kind = &name[(name.find('-').unwrap() + 1)..];
+    match kind {
+        "word" => word(buffer, x, y, around),
+        "double-quote" => quote(buffer, x, y, '"', around),
+        "single-quote" => quote(buffer, x, y, '\'', around),
+        "paren" => bracket(buffer, x, y, '(', ')', count, around),
+        "brace" => bracket(buffer, x, y, '{', '}', count, around),
+        "bracket" => bracket(buffer, x, y, '[', ']', count, around),
+        "angle" => bracket(buffer, x, y, '<', '>', count, around),
+        "tag" => tag(buffer, x, y, count, around),
+        "paragraph" => paragraph(buffer, y, around),
+        "function" => function(buffer, x, y, around),
+        _ => None,
+    }
+}
+
+fn charwise(start: (i32, i32), end: (i32, i32)) -> Region {
+    Region { start: start, end: end, linewise: false }
+}
+
+fn linewise(start: i32, end: i32) -> Region {
+    Region { start: (0, start), end: (0, end), linewise: true }
+}
+
+// Widens [start, end) on line `y` over the blanks after it, or failing
+// that the blanks before it.
+fn take_blanks(buffer: &Buffer, y: i32, start: i32, end: i32) -> (i32, i32) {
+    let ref line = buffer.lines[y as usize];
+    let blank = |i: i32| line[i as usize].ch.is_whitespace();
+    let mut after = end;
+    while after < line.len() as i32 && blank(after) {
+        after += 1;
+    }
+    if after > end {
+        return (start, after);
+    }
+    let mut start = start;
+    while start > 0 && blank(start - 1) {
+        start -= 1;
+    }
+    (start, end)
+}
+
+fn word(buffer: &Buffer, x: i32, y: i32, around: bool) -> Option<Region> {
+    let len = buffer.lines[y as usize].len() as i32;
+    if len == 0 {
+        return None;
+    }
+    let x = min(x, len - 1);
+    let class = |i: i32| motion::class_at(buffer, (i, y), false);
+    let here = class(x);
+    let mut start = x;
+    while start > 0 && class(start - 1) == here {
+        start -= 1;
+    }
+    let mut end = x + 1;
+    while end < len && class(end) == here {
+        end += 1;
+    }
+    if around {
+        if here == BLANK {
+            // the blanks and the word after them
+            if end < len {
+                let next = class(end);
+                while end < len && class(end) == next {
+                    end += 1;
+                }
+            }
+        } else {
+            let (s, e) = take_blanks(buffer, y, start, end);
+            start = s;
+            end = e;
+        }
+    }
+    Some(charwise((start, y), (end, y)))
+}
+
+// Quotes pair up from the start of the line; a backslash escapes one.
+fn quote(buffer: &Buffer, x: i32, y: i32, q: char, around: bool) -> Option<Region> {
+    let ref line = buffer.lines[y as usize];
+    let quotes: Vec<i32> = (0..line.len())
+        .filter(|&i| line[i].ch == q && (i == 0 || line[i - 1].ch != '\\'))
+        .map(|i| i as i32)
+        .collect();
+    let pair = quotes.chunks(2)
+        .filter(|pair| pair.len() == 2)
+        .find(|pair| pair[1] >= x);
+    match pair {
+        Some(pair) if around => {
+            let (start, end) = take_blanks(buffer, y, pair[0], pair[1] + 1);
+            Some(charwise((start, y), (end, y)))
+        },
+        Some(pair) => Some(charwise((pair[0] + 1, y), (pair[1], y))),
+        None => None,
+    }
+}
+
+// Brackets in strings and comments only pair with each other, unless
+// the cursor is in a string that's inside the brackets.
+fn bracket(buffer: &Buffer, x: i32, y: i32, open: char, close: char, count: i32, around: bool) -> Option<Region> {
+    let quoted = syntax::strings_and_comments(buffer);
+    let inside = motion::is_quoted(&quoted, (x, y));
+    let enclosing = |inside: bool| {
+        let mut pos = if buffer.lines[y as usize].get(x as usize).map(|c| c.ch) == Some(open) {
+            Some((x, y))
+        } else {
+            motion::find_bracket(buffer, (x, y), open, close, false, &quoted, inside)
+        };
+        for _ in 1..count {
+            pos = pos.and_then(|p| motion::find_bracket(buffer, p, open, close, false, &quoted, inside));
+        }
+        pos.and_then(|o| motion::find_bracket(buffer, o, open, close, true, &quoted, inside).map(|c| (o, c)))
+    };
+    let (open_pos, close_pos) = match enclosing(inside) {
+        Some(pair) => pair,
+        None if inside => match enclosing(false) {
+            Some(pair) => pair,
+            None => { return None; }
+        },
+        None => { return None; }
+    };
+
+    if around {
+        return Some(charwise(open_pos, (close_pos.0 + 1, close_pos.1)));
+    }
+    // a block's inside leaves out the line breaks after the opening bracket
+    // and the indentation before the closing one
+    let mut start = (open_pos.0 + 1, open_pos.1);
+    if start.0 >= buffer.lines[start.1 as usize].len() as i32 && start.1 < close_pos.1 {
+        start = (0, start.1 + 1);
+    }
+    let mut end = close_pos;
+    let indent_only = buffer.lines[end.1 as usize].iter().take(end.0 as usize).all(|c| c.ch.is_whitespace());
+    if end.1 > start.1 && indent_only {
+        end = (0, end.1);
+    }
+    Some(charwise(start, end))
+}
+
+fn tag(buffer: &Buffer, x: i32, y: i32, count: i32, around: bool) -> Option<Region> {
+    let lines: Vec<String> = buffer.lines.iter().map(|line| line.iter().map(|c| c.ch).collect()).collect();
+    let text = lines.join("\n");
+    let mut line_starts = vec![];
+    let mut offset = 0;
+    for line in &lines {
+        line_starts.push(offset);
+        offset += line.len() + 1;
+    }
+    let cursor = line_starts[y as usize] + lines[y as usize].chars().take(x as usize).map(|c| c.len_utf8()).sum::<usize>();
+    let to_pos = |offset: usize| {
+        let y = line_starts.iter().rposition(|&s| s <= offset).unwrap();
+        ((&text[line_starts[y]..offset]).chars().count() as i32, y as i32)
+    };
+
+    // (open start, open end, close start, close end), in bytes
+    let mut pairs = vec![];
+    let mut open: Vec<(String, usize, usize)> = vec![];
+    for caps in TAG.captures_iter(&text) {
+        let (s, e) = caps.pos(0).unwrap();
+        let name = caps.at(2).unwrap().to_string();
+        if caps.at(3) == Some("/") {
+            continue;
+        }
+        if caps.at(1) == Some("/") {
+            if let Some(i) = open.iter().rposition(|t| t.0 == name) {
+                pairs.push((open[i].1, open[i].2, s, e));
+                open.truncate(i);
+            }
+        } else {
+            open.push((name, s, e));
+        }
+    }
+
+    let mut enclosing: Vec<(usize, usize, usize, usize)> = pairs.into_iter()
+        .filter(|&(a, _, _, d)| a <= cursor && cursor < d)
+        .collect();
+    enclosing.sort_by_key(|&(a, _, _, d)| d - a);
+    match enclosing.get((count - 1) as usize) {
+        Some(&(a, _, _, d)) if around => Some(charwise(to_pos(a), to_pos(d))),
+        Some(&(_, b, c, _)) => Some(charwise(to_pos(b), to_pos(c))),
+        None => None,
+    }
+}
+
+// A paragraph is a run of non-empty lines, or of empty ones.
+fn paragraph(buffer: &Buffer, y: i32, around: bool) -> Option<Region> {
+    let empty = |y: i32| buffer.lines[y as usize].len() == 0;
+    let kind = empty(y);
+    let mut start = y;
+    while start > 0 && empty(start - 1) == kind {
+        start -= 1;
+    }
+    let mut end = y;
+    while end + 1 < buffer.eof() && empty(end + 1) == kind {
+        end += 1;
+    }
+    if around {
+        let last = end;
+        if end + 1 < buffer.eof() {
+            let next = empty(end + 1);
+            while end + 1 < buffer.eof() && empty(end + 1) == next {
+                end += 1;
+            }
+        }
+        if end == last && !kind {
+            while start > 0 && empty(start - 1) {
+                start -= 1;
+            }
+        }
+    }
+    Some(linewise(start, end))
+}
+
+fn indent_of(buffer: &Buffer, y: i32) -> usize {
+    buffer.lines[y as usize].iter().take_while(|c| c.ch.is_whitespace()).count()
+}
+
+// Functions are found by their meta.function scope. The body is the block
+// in braces that follows, or for languages without braces, the lines
+// indented deeper than the definition.
+fn function(buffer: &Buffer, x: i32, y: i32, around: bool) -> Option<Region> {
+    let scope = Scope::new("meta.function").unwrap();
+    let flags = match syntax::scope_map(buffer, |stack| syntax::in_scope(stack, scope)) {
+        Some(flags) => flags,
+        None => { return None; }
+    };
+    let quoted = syntax::strings_and_comments(buffer);
+    let flag = |(x, y): (i32, i32)| flags[y as usize].get(x as usize).cloned().unwrap_or(false);
+
+    // try each definition at or above the cursor, nearest first
+    let mut pos = (min(x, buffer.eol(y)), y);
+    loop {
+        let starts_here = flag(pos) && motion::prev_pos(buffer, pos).map(|p| !flag(p)).unwrap_or(true);
+        if starts_here {
+            if let Some((first, last, inner)) = function_extent(buffer, pos, &flag, &quoted) {
+                if first <= y && y <= last {
+                    return if around { Some(linewise(first, last)) } else { inner };
+                }
+            }
+        }
+        pos = match motion::prev_pos(buffer, pos) {
+            Some(p) => p,
+            None => { return None; }
+        };
+    }
+}
+
+// The first and last lines of the definition starting at `start`, and its body.
+fn function_extent<F>(buffer: &Buffer, start: (i32, i32), flag: &F, quoted: &Option<Vec<Vec<bool>>>)
+                      -> Option<(i32, i32, Option<Region>)> where F: Fn((i32, i32)) -> bool {
+    let mut pos = start;
+    let mut signature_end = start.1;
+    let mut open = None;
+    loop {
+        let ch = buffer.lines[pos.1 as usize].get(pos.0 as usize).map(|c| c.ch);
+        if ch == Some('{') && !motion::is_quoted(quoted, pos) {
+            open = Some(pos);
+            break;
+        }
+        if ch == Some(';') && !motion::is_quoted(quoted, pos) {
+            // a declaration without a body
+            return None;
+        }
+        if flag(pos) {
+            signature_end = pos.1;
+        } else if motion::class_at(buffer, pos, true) != BLANK {
+            break;
+        }
+        pos = match motion::next_pos(buffer, pos) {
+            Some(p) => p,
+            None => { break; }
+        };
+    }
+
+    match open {
+        Some(open) => {
+            let close = match motion::find_bracket(buffer, open, '{', '}', true, quoted, false) {
+                Some(close) => close,
+                None => { return None; }
+            };
+            let inner = if close.1 - open.1 >= 2 {
+                Some(linewise(open.1 + 1, close.1 - 1))
+            } else {
+                Some(charwise((open.0 + 1, open.1), close))
+            };
+            Some((start.1, close.1, inner))
+        },
+        None => {
+            let indent = indent_of(buffer, start.1);
+            let mut last = signature_end;
+            let mut y = signature_end + 1;
+            while y < buffer.eof() {
+                if buffer.lines[y as usize].len() > 0 {
+                    if indent_of(buffer, y) <= indent { break; }
+                    last = y;
+                }
+                y += 1;
+            }
+            let inner = if last > signature_end { Some(linewise(signature_end + 1, last)) } else { None };
+            Some((start.1, last, inner))
+        }
+    }
+}
+
+impl Editor {
+    // Operates on the text object, or selects it in visual mode.
+    pub fn select_text_object(&mut self, name: &str) {
+        let (mode, region) = {
+            let window = self.window_tree.find_active_window().unwrap();
+            let ref buffer = self.buffers[window.buffer_index as usize];
+            let count = if window.mode == "operator" {
+                window.take_operator_count()
+            } else {
+                window.count.take()
+            };
+            (window.mode.clone(), find(buffer, name, window.cursor_x, window.cursor_y, count.unwrap_or(1)))
+        };
+        match (mode.as_str(), region) {
+            ("operator", Some(region)) => {
+                let operator = self.window_tree.find_active_window().unwrap().operator.take();
+                if let Some(operator) = operator {
+                    self.apply_operator(&operator, region);
+                }
+            },
+            ("operator", None) => { self.window_tree.find_active_window().unwrap().cancel_operator(); },
+            ("visual", Some(region)) | ("visual_line", Some(region)) => {
+                let window = self.window_tree.find_active_window().unwrap();
+                let ref buffer = self.buffers[window.buffer_index as usize];
+                let (start, last) = if region.linewise {
+                    ((0, region.start.1), (buffer.eol(region.end.1), region.end.1))
+                } else if region.end.0 > 0 {
+                    (region.start, (region.end.0 - 1, region.end.1))
+                } else if region.end.1 > region.start.1 {
+                    // ends with a line break
+                    let y = region.end.1 - 1;
+                    (region.start, (buffer.lines[y as usize].len() as i32, y))
+                } else {
+                    (region.start, region.start)
+                };
+                window.mark = Some((start.1, start.0));
+                window.cursor_x = last.0;
+                window.cursor_y = last.1;
+                window.col = last.0;
+                window.row = last.1;
+            },
+            _ => ()
+        }
+    }
+}
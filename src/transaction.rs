@@ -3,4 +3,5 @@ pub struct Transaction {
     pub y: i32,
     pub add: bool,
     pub text: String,
+    pub group: usize,
 }
@@ -1,4 +1,22 @@
 use regex::Regex;
+use std::env;
+use std::path::PathBuf;
+use ncurses::*;
+
+pub fn config_dir() -> PathBuf {
+    match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir).join("earthmacs"),
+        Err(_) => PathBuf::from(env::var("HOME").unwrap_or(".".to_string())).join(".config/earthmacs"),
+    }
+}
+
+// The screen minus the message line at the bottom.
+pub fn window_area() -> (i32, i32) {
+    let mut max_y = 0;
+    let mut max_x = 0;
+    getmaxyx(stdscr(), &mut max_y, &mut max_x);
+    (max_x, max_y - 1)
+}
 
 pub fn rgb_to_short(rgb: &str) -> usize {
     let matches = RE.captures(rgb).unwrap();
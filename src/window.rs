@@ -13,6 +13,21 @@ pub struct Window {
     pub mode: String,
     pub active: bool,
     pub mark: Option<(i32, i32)>,
+    // pending count typed before a command
+    pub count: Option<i32>,
+    // operator waiting for a motion, and the count typed before it
+    pub operator: Option<String>,
+    pub operator_count: Option<i32>,
+    // the register typed after " for the next yank, delete or paste, and
+    // the mode to go back to once it's typed
+    pub register: Option<char>,
+    pub register_mode: Option<String>,
+    // more cursors besides the one at (cursor_y, cursor_x), as (y, x)
+    pub cursors: Vec<(i32, i32)>,
+    // places jumped from as (buffer index, y, x), oldest first, and where
+    // <C-o> and <C-i> have got to in them
+    pub jumps: Vec<(i32, i32, i32)>,
+    pub jump_index: usize,
 }
 
 impl Window {
@@ -28,6 +43,14 @@ impl Window {
             mode: "normal".to_string(),
             active: false,
             mark: None,
+            count: None,
+            operator: None,
+            operator_count: None,
+            register: None,
+            register_mode: None,
+            cursors: vec![],
+            jumps: vec![],
+            jump_index: 0,
         }
     }
 
@@ -67,6 +90,60 @@ impl Window {
         self.scroll_y -= 1;
     }
 
+    pub fn take_count(&mut self) -> i32 {
+        self.count.take().unwrap_or(1)
+    }
+
+    // The count typed before an operator times the one typed before its
+    // motion, as in 2d3w.
+    pub fn take_operator_count(&mut self) -> Option<i32> {
+        match (self.operator_count.take(), self.count.take()) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(1).saturating_mul(b.unwrap_or(1))),
+        }
+    }
+
+    // v, V and <C-v> selections.
+    pub fn in_visual_mode(&self) -> bool {
+        self.mode == "visual" || self.mode == "visual_line" || self.mode == "visual_block"
+    }
+
+    // Shows another buffer from its start. Extra cursors and a selection
+    // belong to the buffer shown before, so they go.
+    pub fn show_buffer(&mut self, index: i32) {
+        if index != self.buffer_index {
+            self.cursors.clear();
+            self.mark = None;
+            if self.in_visual_mode() {
+                self.mode = "normal".to_string();
+            }
+        }
+        self.buffer_index = index;
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.col = 0;
+        self.row = 0;
+        self.scroll_y = 0;
+    }
+
+    pub fn cancel_operator(&mut self) {
+        self.operator = None;
+        self.operator_count = None;
+        self.count = None;
+        self.register = None;
+        self.mode = "normal".to_string();
+    }
+
+    // Scrolls just enough for the cursor to be visible in a window `height`
+    // rows tall, borders included.
+    pub fn scroll_to_cursor(&mut self, height: i32) {
+        if self.cursor_y < self.scroll_y {
+            self.scroll_y = self.cursor_y;
+        } else if self.cursor_y >= self.scroll_y + height - 2 {
+            self.scroll_y = self.cursor_y - (height - 3);
+        }
+    }
+
     pub fn calc_mark_region(&self) -> Option<((i32, i32), (i32, i32))> {
         match self.mark {
             Some(mark) => {
@@ -1,10 +1,15 @@
-use std::cmp::{max};
+use std::cmp::{min, max};
 use buffer::Buffer;
 use window::Window;
 use ncurses::*;
+use regex::Regex;
+use search;
+use block;
+use block::Block;
 
 static COLOR_PAIR_DEFAULT: i16 = 1;
 static COLOR_PAIR_HIGHLIGHT: i16 = 2;
+static COLOR_PAIR_SEARCH: i16 = 3;
 
 const NORTH: usize = 1;
 const SOUTH: usize = 2;
@@ -164,7 +169,8 @@ impl WindowTree {
         }
     }
 
-    pub fn draw(&mut self, buffers: &Vec<Buffer>, width: i32, height: i32, x: i32, y: i32) {
+    // Matches of `pattern` are highlighted.
+    pub fn draw(&mut self, buffers: &Vec<Buffer>, pattern: Option<&Regex>, width: i32, height: i32, x: i32, y: i32) {
         let n = self.branches.len() as i32;
         if n > 0 {
             let mut extra_width = 0;
@@ -175,17 +181,33 @@ impl WindowTree {
                     extra_height = height % n;
                 }
                 if self.direction.as_str() == "horizontal" {
-                    branch.draw(buffers, (width / n) + extra_width, height, x + ((width / n) * (i as i32)), y);
+                    branch.draw(buffers, pattern, (width / n) + extra_width, height, x + ((width / n) * (i as i32)), y);
                 } else {
-                    branch.draw(buffers, width, (height / n) + extra_height, x, y + ((height / n) * (i as i32)))
+                    branch.draw(buffers, pattern, width, (height / n) + extra_height, x, y + ((height / n) * (i as i32)))
                 }
             }
         } else {
             let ref buffer = buffers[self.leaf.buffer_index as usize];
+            let tab_width = buffer.settings.tab_width;
             let mut lines = buffer.lines.iter().skip(self.leaf.scroll_y as usize).take(height as usize);
             let mut has_mark = false;
             let mut starts_with_mark = false;
+            // V selects whole lines, drawn the full width of the window, and
+            // <C-v> a rectangle of screen columns
+            let selected_block = match self.leaf.mark {
+                Some(mark) if self.leaf.mode == "visual_line" => Some(Block {
+                    top: min(mark.0, self.leaf.cursor_y),
+                    bottom: max(mark.0, self.leaf.cursor_y),
+                    left: 0,
+                    right: width,
+                }),
+                Some(mark) if self.leaf.mode == "visual_block" => {
+                    Some(block::selection(buffer, mark, (self.leaf.cursor_y, self.leaf.cursor_x)))
+                },
+                _ => None,
+            };
             match self.leaf.mark {
+                Some(_) if selected_block.is_some() => (),
                 Some(mark) => {
                     has_mark = true;
                     if mark.0 == self.leaf.cursor_y {
@@ -205,9 +227,17 @@ impl WindowTree {
 
                 match lines.next() {
                     Some(line) => {
+                        let row = y + self.leaf.scroll_y;
                         let mut cells = line.iter();
+                        let matches = match pattern {
+                            Some(re) => search::match_ranges(re, &line.iter().map(|c| c.ch).collect::<String>()),
+                            None => vec![],
+                        };
 
-                        for x in 0..width {
+                        // x is the index into the line, col the screen column
+                        let mut x = 0;
+                        let mut col = 0;
+                        while col < width {
 
                             // calc mark region
                             if has_mark {
@@ -228,29 +258,58 @@ impl WindowTree {
 
                             // highlight mark region
                             // and print cell
+                            let in_block = selected_block.map(|b| row >= b.top && row <= b.bottom && col >= b.left && col <= b.right);
+                            let selected = marking || in_block.unwrap_or(false);
+                            // the other cursors are drawn in reverse
+                            let on_cursor = self.leaf.cursors.contains(&(row, x as i32));
+                            if on_cursor {
+                                wattron(self.leaf.pane, A_REVERSE());
+                            }
                             if let Some(ch) = cells.next() {
-                                if marking {
+                                let matched = matches.iter().any(|&(start, end)| x >= start && x < end);
+                                if selected {
                                     wattroff(self.leaf.pane, COLOR_PAIR(ch.fg as i16));
+                                    wattroff(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_SEARCH));
                                     wattron(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_HIGHLIGHT));
+                                } else if matched {
+                                    wattroff(self.leaf.pane, COLOR_PAIR(ch.fg as i16));
+                                    wattroff(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_HIGHLIGHT));
+                                    wattron(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_SEARCH));
                                 } else {
                                     wattroff(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_HIGHLIGHT));
+                                    wattroff(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_SEARCH));
                                     wattron(self.leaf.pane, COLOR_PAIR(ch.fg as i16));
                                 }
-                                waddstr(self.leaf.pane, ch.ch.to_string().as_str());
+                                if ch.ch == '\t' {
+                                    let tab = tab_width - col % tab_width;
+                                    waddstr(self.leaf.pane, (0..min(tab, width - col)).map(|_| " ").collect::<String>().as_str());
+                                    col += tab;
+                                } else {
+                                    waddstr(self.leaf.pane, ch.ch.to_string().as_str());
+                                    col += 1;
+                                }
                             } else {
-                                if marking {
+                                wattroff(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_SEARCH));
+                                if selected {
                                     wattron(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_HIGHLIGHT));
                                 } else {
                                     wattroff(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_HIGHLIGHT));
                                 }
                                 waddstr(self.leaf.pane, " ");
+                                col += 1;
                             }
+                            if on_cursor {
+                                wattroff(self.leaf.pane, A_REVERSE());
+                            }
+                            x += 1;
                         }
                     },
                     None => ()
                 }
             }
 
+            wattroff(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_SEARCH));
+
             // position/size
             wresize(self.leaf.pane, height, width);
             mvwin(self.leaf.pane, y, x);
@@ -275,10 +334,14 @@ impl WindowTree {
                 waddstr(self.leaf.pane, xy_label.as_str());
             }
 
-            if width >= self.leaf.mode.len() as i32 + 4 {
-                // mode label
-                wmove(self.leaf.pane, height - 1, width - 4 - self.leaf.mode.len() as i32);
-                waddstr(self.leaf.pane, self.leaf.mode.as_str());
+            // mode label, with any pending count
+            let mode_label = match self.leaf.count {
+                Some(count) => format!("{} {}", count, self.leaf.mode),
+                None => self.leaf.mode.clone(),
+            };
+            if width >= mode_label.len() as i32 + 4 {
+                wmove(self.leaf.pane, height - 1, width - 4 - mode_label.len() as i32);
+                waddstr(self.leaf.pane, mode_label.as_str());
             }
 
             wattroff(self.leaf.pane, COLOR_PAIR(COLOR_PAIR_DEFAULT));
@@ -0,0 +1,134 @@
+use buffer::Buffer;
+use comment;
+
+// The columns `text` takes up, with tabs at the buffer's tab stops.
+fn width(buffer: &Buffer, text: &str) -> i32 {
+    let tab_width = buffer.settings.tab_width;
+    text.chars().fold(0, |column, ch| if ch == '\t' { column + tab_width - column % tab_width } else { column + 1 })
+}
+
+// A list bullet at the start of `text` with the space after it: `-`, `*`,
+// `+`, `1.` or `1)`.
+fn bullet(text: &str) -> Option<String> {
+    let digits = text.chars().take_while(|c| c.is_digit(10)).count();
+    let marker = match text.chars().nth(digits) {
+        Some('.') | Some(')') if digits > 0 => digits + 1,
+        Some('-') | Some('*') | Some('+') if digits == 0 => 1,
+        _ => { return None; }
+    };
+    let space = text.chars().skip(marker).take_while(|c| c.is_whitespace()).count();
+    if space == 0 {
+        return None;
+    }
+    Some(text.chars().take(marker + space).collect())
+}
+
+// What starts line `text` before its words: indentation, a comment leader
+// and the space after it, and a list bullet. The second is the same for
+// the lines the first continues onto, with the bullet blanked out.
+pub fn prefixes(buffer: &Buffer, text: &str) -> (String, String) {
+    let indent: String = text.chars().take_while(|c| c.is_whitespace()).collect();
+    let mut rest = &text[indent.len()..];
+    let mut prefix = indent;
+    if let Some(leader) = comment::leader(&buffer.settings.comment, rest) {
+        rest = &rest[leader.len()..];
+        prefix.push_str(&leader);
+        let space: String = rest.chars().take_while(|c| c.is_whitespace()).collect();
+        rest = &rest[space.len()..];
+        prefix.push_str(&space);
+    }
+    match bullet(rest) {
+        Some(bullet) => {
+            let blank: String = bullet.chars().map(|_| ' ').collect();
+            (format!("{}{}", prefix, bullet), format!("{}{}", prefix, blank))
+        },
+        None => (prefix.clone(), prefix),
+    }
+}
+
+// Fills lines first to last up to the text width a paragraph at a time,
+// keeping each paragraph's indentation, comment leader and bullet. Blank
+// lines, a change of leader and a new bullet each start a paragraph.
+// Returns where the last line ends up.
+pub fn reflow(buffer: &mut Buffer, first: i32, last: i32) -> i32 {
+    let text_width = buffer.settings.text_width;
+    let mut last = last;
+    let mut y = first;
+    while y <= last {
+        let text = buffer.line_text(y);
+        let (prefix, continuation) = prefixes(buffer, &text);
+        let mut words: Vec<String> = text[prefix.len()..].split_whitespace().map(|w| w.to_string()).collect();
+        if words.len() == 0 {
+            y += 1;
+            continue;
+        }
+        let mut end = y;
+        while end < last {
+            let next = buffer.line_text(end + 1);
+            let (next_prefix, next_continuation) = prefixes(buffer, &next);
+            let body: Vec<String> = next[next_prefix.len()..].split_whitespace().map(|w| w.to_string()).collect();
+            if next_prefix != next_continuation || next_prefix.trim() != continuation.trim() || body.len() == 0 {
+                break;
+            }
+            words.extend(body);
+            end += 1;
+        }
+
+        let mut lines = vec![];
+        let mut line = prefix.clone();
+        let mut empty = true;
+        for word in words {
+            if !empty && width(buffer, &line) + 1 + width(buffer, &word) > text_width {
+                lines.push(line);
+                line = continuation.clone();
+                empty = true;
+            }
+            if !empty {
+                line.push(' ');
+            }
+            line.push_str(&word);
+            empty = false;
+        }
+        lines.push(line);
+
+        let old = buffer.text_between((0, y), (buffer.lines[end as usize].len() as i32, end));
+        let new = lines.join("\n");
+        if old != new {
+            buffer.remove_text(0, y, old.chars().count(), true);
+            buffer.insert(&new, 0, y, true);
+        }
+        let count = lines.len() as i32;
+        last += count - (end - y + 1);
+        y += count;
+    }
+    last
+}
+
+// Breaks line y at the last space that leaves it within the text width,
+// after a character typed at x takes it past. The rest goes on a new line
+// with the same indentation and comment leader. Returns where the cursor
+// ends up.
+pub fn auto_wrap(buffer: &mut Buffer, x: i32, y: i32) -> Option<(i32, i32)> {
+    let text = buffer.line_text(y);
+    let chars: Vec<char> = text.chars().collect();
+    if !buffer.settings.auto_wrap || width(buffer, &text) <= buffer.settings.text_width {
+        return None;
+    }
+    let (prefix, continuation) = prefixes(buffer, &text);
+    let start = prefix.chars().count();
+    // the last space before the word that crosses the text width, or
+    // failing that the first after it, as a long word can't be split
+    let fits = |i: usize| width(buffer, &chars[..i].iter().cloned().collect::<String>()) <= buffer.settings.text_width;
+    let spaces: Vec<usize> = (start..chars.len()).filter(|&i| chars[i].is_whitespace() && (i == 0 || !chars[i - 1].is_whitespace())).collect();
+    let space = match spaces.iter().rev().find(|&&i| fits(i)).or(spaces.first()) {
+        Some(&space) if (space as i32) < x => space,
+        _ => { return None; }
+    };
+    let after = (space..chars.len()).find(|&i| !chars[i].is_whitespace()).unwrap_or(chars.len());
+    buffer.begin_group();
+    buffer.remove_text(space as i32, y, after - space, true);
+    buffer.insert(&format!("\n{}", continuation), space as i32, y, true);
+    buffer.end_group();
+    let column = continuation.chars().count() as i32 + x - after as i32;
+    Some((column, y + 1))
+}